@@ -3,11 +3,24 @@
 
 //! CLI argument parsing and Zenoh configuration.
 
-use clap::Parser;
+use crate::{
+    camera::{CameraOrientation, CameraOutputs, DmaSource, LoopReset},
+    camera_info::CalibrationFile,
+    image_publish::{ImageFit, ImageFlip, ImageFormat, ImageRoi, ImageRotation, ImageSize},
+    info::ListFormat,
+    late::{LatePolicy, TopicLatePolicy},
+    remap::{RemapRule, TopicPrefix},
+    timing::Timing,
+    video_decode::DecoderBackend,
+};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
 use serde_json::json;
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tracing::level_filters::LevelFilter;
-use zenoh::{config::WhatAmI, key_expr::OwnedKeyExpr, Config};
+use zenoh::{config::WhatAmI, Config};
 
 /// Command-line arguments for EdgeFirst Replay Node.
 ///
@@ -30,48 +43,291 @@ use zenoh::{config::WhatAmI, key_expr::OwnedKeyExpr, Config};
 #[derive(Debug, Clone, Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Path to the MCAP recording file to replay
-    #[arg(env = "MCAP", required = true)]
-    pub mcap: PathBuf,
+    /// MCAP recordings to replay: files, directories or glob patterns
+    /// (space-delimited in the environment). Multiple files are ordered by
+    /// start time and replayed as one continuous timeline
+    #[arg(env = "MCAP", required = true, num_args = 1..)]
+    pub mcap: Vec<PathBuf>,
+
+    /// Remove the gaps between consecutive recordings of a playlist instead
+    /// of waiting them out
+    #[arg(long, env = "COLLAPSE_GAPS")]
+    pub collapse_gaps: bool,
+
+    /// Clock skew corrections as `file=offset` (space-delimited), added to
+    /// the log times of recordings whose path or file name matches the glob
+    /// pattern `file`, e.g. `radar-*.mcap=-250ms`
+    #[arg(long, env = "TIME_OFFSET", value_delimiter = ' ', value_parser = parse_time_offset)]
+    pub time_offset: Vec<TimeOffset>,
 
     /// Replay speed multiplier (must be greater than 0)
     #[arg(short, long, env = "REPLAY_SPEED", default_value = "1.0", value_parser = parse_replay_speed)]
     pub replay_speed: f64,
 
+    /// Replay as fast as possible, ignoring log time pacing and
+    /// --replay-speed
+    #[arg(long, env = "AFAP")]
+    pub afap: bool,
+
+    /// Time to pace messages by: the MCAP `log` time, the MCAP `publish`
+    /// time, or the `header` stamp of messages with a known header (others
+    /// keep their log time)
+    #[arg(long, env = "TIMING", value_enum, default_value = "log")]
+    pub timing: Timing,
+
+    /// Zenoh topic on which downstream consumers acknowledge each message.
+    /// Setting it enables lockstep replay: after publishing a message on
+    /// --lockstep-topics, replay waits for the acknowledgement before
+    /// publishing the next one. Implies --afap
+    #[arg(long, env = "LOCKSTEP_ACK_TOPIC", default_value = "")]
+    pub lockstep_ack_topic: String,
+
+    /// Consumers that must all acknowledge each message, identified by the
+    /// name they publish on --lockstep-ack-topic (space-delimited; empty =
+    /// one acknowledgement from anyone)
+    #[arg(long, env = "LOCKSTEP_CONSUMERS", value_delimiter = ' ')]
+    pub lockstep_consumers: Vec<String>,
+
+    /// Replayed topics whose messages wait for acknowledgements in lockstep
+    /// replay (space-delimited; empty = every topic)
+    #[arg(long, env = "LOCKSTEP_TOPICS", value_delimiter = ' ', value_parser = parse_topics)]
+    pub lockstep_topics: Vec<Option<String>>,
+
+    /// Give up waiting for a lockstep acknowledgement after this long and
+    /// carry on (e.g. `10s`); `0` waits indefinitely
+    #[arg(long, env = "LOCKSTEP_TIMEOUT", default_value = "30s", value_parser = parse_duration)]
+    pub lockstep_timeout: Duration,
+
+    /// Start replay at this point in the recording. Either an offset from the
+    /// first message (`90`, `90s`, `1500ms`, `40m`, `1.5h`) or an absolute
+    /// log time in nanoseconds prefixed with `@` (e.g. `@1716400000000000000`)
+    #[arg(long, env = "START", value_parser = parse_time_spec)]
+    pub start: Option<TimeSpec>,
+
+    /// Stop replay at this point in the recording (same syntax as --start)
+    #[arg(long, env = "END", value_parser = parse_time_spec)]
+    pub end: Option<TimeSpec>,
+
+    /// What to do with messages the replay loop reaches behind schedule:
+    /// `publish` them late, `drop:<age>` those further behind than `age`
+    /// (e.g. `drop:50ms`), or drop all but the `latest` of a topic's due
+    /// messages
+    #[arg(long, env = "LATE_POLICY", default_value = "publish")]
+    pub late_policy: LatePolicy,
+
+    /// Late policies for individual topics, as `topic=policy`
+    /// (space-delimited), overriding --late-policy
+    #[arg(long, env = "TOPIC_LATE_POLICIES", value_delimiter = ' ')]
+    pub topic_late_policy: Vec<TopicLatePolicy>,
+
+    /// Zenoh queryable for runtime playback control (pause, resume, step,
+    /// seek, speed). Empty disables runtime control.
+    #[arg(long, env = "CONTROL_TOPIC", default_value = "/replay/control")]
+    pub control_topic: String,
+
+    /// Zenoh topic for JSON replay status, progress and loop/end events.
+    /// Empty disables status publishing.
+    #[arg(long, env = "STATUS_TOPIC", default_value = "/replay/status")]
+    pub status_topic: String,
+
+    /// Interval between periodic status messages (e.g. `1s`, `250ms`)
+    #[arg(long, env = "STATUS_INTERVAL", default_value = "1s", value_parser = parse_interval)]
+    pub status_interval: Duration,
+
+    /// Publish simulated time derived from the playback clock as
+    /// rosgraph_msgs/Clock for sim-time aware consumers
+    #[arg(long, env = "CLOCK")]
+    pub clock: bool,
+
+    /// Zenoh topic for the simulated clock
+    #[arg(long, env = "CLOCK_TOPIC", default_value = "/clock")]
+    pub clock_topic: String,
+
+    /// Simulated clock publish rate in Hz (wall time)
+    #[arg(long, env = "CLOCK_RATE", default_value = "40", value_parser = parse_rate)]
+    pub clock_rate: f64,
+
+    /// Rewrite header stamps to the current wall time at which each message
+    /// is replayed, preserving the recorded spacing scaled by replay speed
+    #[arg(long, env = "RESTAMP")]
+    pub restamp: bool,
+
+    /// Remap output topics, as `from=to` key expressions (space-delimited).
+    /// Wildcards in `to` take the chunks matched by the wildcards in `from`,
+    /// e.g. `rt/camera/**=rt/front/**`
+    #[arg(long, env = "REMAP", value_delimiter = ' ')]
+    pub remap: Vec<RemapRule>,
+
+    /// Namespace inserted after the topic prefix in every published topic, e.g.
+    /// `replayA` publishes `rt/camera/h264` as `rt/replayA/camera/h264`
+    #[arg(long, env = "NAMESPACE", default_value = "")]
+    pub namespace: String,
+
     /// Zenoh topic for raw DMA buffer metadata
-    #[arg(long, default_value = "rt/camera/dma")]
+    #[arg(long, default_value = "/camera/dma")]
     pub dma_topic: String,
 
-    /// Zenoh topic for hal-decoded RGBA sensor_msgs/Image output (e.g.
-    /// `rt/camera/image`). Empty disables this side channel; the camera-native
-    /// NV12 DmaBuffer publish on `--dma-topic` is unaffected.
+    /// Zenoh topic for hal-decoded sensor_msgs/Image output in
+    /// --camera-image-format (e.g. `rt/camera/image`). Empty disables this
+    /// side channel; the camera-native NV12 DmaBuffer publish on
+    /// `--dma-topic` is unaffected.
     #[arg(long, env = "CAMERA_IMAGE_TOPIC", default_value = "")]
     pub camera_image_topic: String,
 
-    /// Number of RGBA destination buffers pre-allocated for the hal image
+    /// Number of image destination buffers pre-allocated for the hal image
     /// path. Ignored when --camera-image-topic is empty.
     #[arg(long, env = "CAMERA_IMAGE_BUFFERS", default_value = "4")]
     pub camera_image_buffers: usize,
 
-    /// List all topics in the MCAP file and exit
+    /// Pixel format of the decoded image topic, written as its ROS encoding:
+    /// rgba8, rgb8, bgra8, bgr8, mono8, yuyv (yuv422_yuy2) or planar-rgb
+    /// (channels-first, as the EdgeFirst-specific rgb8_planar encoding that
+    /// standard ROS consumers do not understand)
+    #[arg(long, env = "CAMERA_IMAGE_FORMAT", value_enum, default_value = "rgba8")]
+    pub camera_image_format: ImageFormat,
+
+    /// Size of the image as WxH, e.g. a model's input size. Defaults to the
+    /// visible frame size, or the --camera-image-roi size
+    #[arg(long, env = "CAMERA_IMAGE_SIZE")]
+    pub camera_image_size: Option<ImageSize>,
+
+    /// How the frame is scaled into --camera-image-size: stretch, letterbox
+    /// (keep the aspect ratio, pad with black) or crop-center (keep the
+    /// aspect ratio, crop the source)
+    #[arg(long, env = "CAMERA_IMAGE_FIT", value_enum, default_value = "stretch")]
+    pub camera_image_fit: ImageFit,
+
+    /// Source region of the image as left,top,width,height in pixels of
+    /// the visible frame. Defaults to the whole frame
+    #[arg(long, env = "CAMERA_IMAGE_ROI")]
+    pub camera_image_roi: Option<ImageRoi>,
+
+    /// Clockwise rotation of the image in degrees: 0, 90, 180 or 270.
+    /// Applied after --camera-image-flip
+    #[arg(long, env = "CAMERA_IMAGE_ROTATION", value_enum, default_value = "0")]
+    pub camera_image_rotation: ImageRotation,
+
+    /// Mirroring of the image: none, horizontal or vertical
+    #[arg(long, env = "CAMERA_IMAGE_FLIP", value_enum, default_value = "none")]
+    pub camera_image_flip: ImageFlip,
+
+    /// Zenoh topic for sensor_msgs/CameraInfo published with every decoded
+    /// frame (e.g. `rt/camera/camera_info`). Empty disables camera_info
+    /// output
+    #[arg(long, env = "CAMERA_INFO_TOPIC", default_value = "")]
+    pub camera_info_topic: String,
+
+    /// Calibration files (YAML or JSON) for camera_info, as `[source=]path`
+    /// (space-delimited). A path without a source applies to every camera
+    /// without its own; cameras without a file use a calibration attached
+    /// to the recording
+    #[arg(long, env = "CAMERA_CALIBRATION", value_delimiter = ' ')]
+    pub camera_calibration: Vec<CalibrationFile>,
+
+    /// Zenoh topic for decoded frames re-encoded as JPEG
+    /// sensor_msgs/CompressedImage (e.g. `rt/camera/jpeg`), for viewers that
+    /// cannot take raw images or DMA buffers. Empty disables it
+    #[arg(long, env = "CAMERA_JPEG_TOPIC", default_value = "")]
+    pub camera_jpeg_topic: String,
+
+    /// JPEG quality of --camera-jpeg-topic, from 1 to 100
+    #[arg(long, env = "CAMERA_JPEG_QUALITY", default_value = "80", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub camera_jpeg_quality: u8,
+
+    /// Divide the width and height of --camera-jpeg-topic frames by this
+    /// factor, e.g. 2 for a quarter of the pixels
+    #[arg(long, env = "CAMERA_JPEG_DOWNSCALE", default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub camera_jpeg_downscale: u32,
+
+    /// Camera channels decoded for --dma-topic: auto for every
+    /// CompressedVideo channel and the CompressedImage channels of cameras
+    /// without one, or a space-delimited list of topics
+    #[arg(long, env = "DMA_SOURCE", default_value = "auto")]
+    pub dma_source: DmaSource,
+
+    /// Output topics for individual camera streams, as
+    /// `source=dma_topic[,image_topic]` (space-delimited). Unlisted streams
+    /// publish on --dma-topic and --camera-image-topic when the recording
+    /// has one camera, or next to their source topic when it has several
+    #[arg(long, env = "CAMERA_STREAMS", value_delimiter = ' ')]
+    pub camera_stream: Vec<CameraOutputs>,
+
+    /// Image rotation and flip for individual camera streams, as
+    /// `source=rotation[,flip]` (space-delimited), overriding
+    /// --camera-image-rotation and --camera-image-flip
+    #[arg(long, env = "CAMERA_IMAGE_ORIENTATION", value_delimiter = ' ')]
+    pub camera_image_orientation: Vec<CameraOrientation>,
+
+    /// Video decoder: the VPU, software (OpenH264 for H.264, libde265 for
+    /// H.265), or auto to use the VPU when available and fall back to
+    /// software otherwise
+    #[arg(long, env = "DECODER", value_enum, default_value = "auto")]
+    pub decoder: DecoderBackend,
+
+    /// Camera messages each stream's decode thread may queue before the
+    /// replay loop waits for it
+    #[arg(long, env = "DECODE_QUEUE", default_value = "4", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub decode_queue: usize,
+
+    /// Describe the recordings and their channels (schema, message count,
+    /// timing, rates and size) and exit
     #[arg(short, long)]
     pub list: bool,
 
+    /// Output format for --list
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: ListFormat,
+
+    /// With --list, read every message for each channel's first and last
+    /// log time, maximum rate and size, even when the summary has
+    /// statistics. Slow on large recordings
+    #[arg(long)]
+    pub scan: bool,
+
     /// Replay the MCAP file only once (no looping)
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with = "loops")]
     pub one_shot: bool,
 
+    /// Replay the recording this many times, then exit (default: loop
+    /// forever)
+    #[arg(long, env = "LOOPS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub loops: Option<u64>,
+
+    /// Pause between loops (e.g. `2s`)
+    #[arg(long, env = "LOOP_GAP", default_value = "0", value_parser = parse_duration)]
+    pub loop_gap: Duration,
+
+    /// Camera stream state reset at the start of every loop: the
+    /// `decoders`, or `all` to also free the image publishers' destination
+    /// buffers
+    #[arg(long, env = "LOOP_RESET", value_enum, default_value = "decoders")]
+    pub loop_reset: LoopReset,
+
+    /// Zenoh topic for a std_msgs/Header marker published at the start of
+    /// every loop, stamped with the loop's start time (rewritten like the
+    /// messages with --restamp) and carrying the loop number as its
+    /// frame_id. Empty disables the marker.
+    #[arg(long, env = "LOOP_TOPIC", default_value = "")]
+    pub loop_topic: String,
+
     /// Stop system services before replay
     #[arg(short, long)]
     pub system: bool,
 
     /// Zenoh topics to publish (space-delimited; empty = publish all)
     #[arg(short, long, env = "TOPICS", value_delimiter = ' ', value_parser = parse_topics)]
-    pub topics: Vec<Option<OwnedKeyExpr>>,
+    pub topics: Vec<Option<String>>,
 
     /// Zenoh topics to ignore during replay (space-delimited)
     #[arg(short, long, env = "IGNORE_TOPICS", required = false, value_delimiter = ' ', value_parser = parse_topics)]
-    pub ignore_topics: Vec<Option<OwnedKeyExpr>>,
+    pub ignore_topics: Vec<Option<String>>,
+
+    /// Prefix added to recorded topics to form Zenoh keys: `rt`, `none` or a
+    /// custom key. Topics already carrying the prefix are not prefixed
+    /// again, and `/`-prefixed topics, including the defaults of replay's
+    /// own topics, expand to it.
+    #[arg(long, env = "TOPIC_PREFIX", default_value = "rt")]
+    pub topic_prefix: TopicPrefix,
 
     /// Application log level
     #[arg(long, env = "RUST_LOG", default_value = "info")]
@@ -98,6 +354,39 @@ pub struct Args {
     no_multicast_scouting: bool,
 }
 
+impl Args {
+    /// Parse the command line and environment, exiting with a usage
+    /// message on error.
+    pub fn load() -> Self {
+        Self::from_matches(&Self::command().get_matches()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Build the arguments from parsed matches. Recordings given on the
+    /// command line are taken whole, so their paths may contain spaces; only
+    /// the `MCAP` environment variable is split on spaces.
+    fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut args = Self::from_arg_matches(matches)?;
+        if matches.value_source("mcap") == Some(ValueSource::EnvVariable) {
+            args.mcap = split_paths(&args.mcap);
+        }
+        Ok(args)
+    }
+}
+
+/// Split the space-delimited `MCAP` environment variable into paths.
+fn split_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|p| {
+            p.to_string_lossy()
+                .split(' ')
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
 fn parse_replay_speed(s: &str) -> Result<f64, String> {
     let speed: f64 = s
         .parse()
@@ -111,20 +400,120 @@ fn parse_replay_speed(s: &str) -> Result<f64, String> {
     Ok(speed)
 }
 
+/// A point in the recording given on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSpec {
+    /// Offset from the first message in the recording.
+    Offset(Duration),
+    /// Absolute MCAP log time in nanoseconds.
+    Absolute(u64),
+}
+
+impl TimeSpec {
+    /// Resolve to an absolute log time given the recording's first log time.
+    pub fn resolve(self, file_start: u64) -> u64 {
+        match self {
+            TimeSpec::Offset(d) => file_start.saturating_add(d.as_nanos() as u64),
+            TimeSpec::Absolute(t) => t,
+        }
+    }
+}
+
+pub fn parse_time_spec(s: &str) -> Result<TimeSpec, String> {
+    if let Some(ns) = s.strip_prefix('@') {
+        return ns
+            .parse()
+            .map(TimeSpec::Absolute)
+            .map_err(|_| format!("'{ns}' is not a valid log time in nanoseconds"));
+    }
+    parse_duration(s).map(TimeSpec::Offset)
+}
+
+/// Clock skew correction for the recordings whose path or file name
+/// matches a glob pattern.
+#[derive(Debug, Clone)]
+pub struct TimeOffset {
+    pub pattern: glob::Pattern,
+    /// Nanoseconds added to the matching recordings' log times.
+    pub offset: i64,
+}
+
+impl TimeOffset {
+    pub fn matches(&self, path: &Path) -> bool {
+        self.pattern.matches_path(path)
+            || path
+                .file_name()
+                .is_some_and(|n| self.pattern.matches(&n.to_string_lossy()))
+    }
+}
+
+/// Parse `file=offset`, where the offset is a duration with an optional sign.
+pub fn parse_time_offset(s: &str) -> Result<TimeOffset, String> {
+    let (pattern, offset) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("time offset '{s}' must have the form file=offset"))?;
+    let pattern = glob::Pattern::new(pattern)
+        .map_err(|e| format!("'{pattern}' is not a valid file pattern: {e}"))?;
+    let (sign, magnitude) = match offset.strip_prefix('-') {
+        Some(v) => (-1, v),
+        None => (1, offset.strip_prefix('+').unwrap_or(offset)),
+    };
+    let nanos = parse_duration(magnitude)?.as_nanos() as i64;
+    Ok(TimeOffset {
+        pattern,
+        offset: sign * nanos,
+    })
+}
+
+/// Parse a non-negative duration in seconds with an optional `ms`, `s`, `m`
+/// or `h` unit suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, scale) = if let Some(v) = s.strip_suffix("ms") {
+        (v, 1e-3)
+    } else if let Some(v) = s.strip_suffix('s') {
+        (v, 1.0)
+    } else if let Some(v) = s.strip_suffix('m') {
+        (v, 60.0)
+    } else if let Some(v) = s.strip_suffix('h') {
+        (v, 3600.0)
+    } else {
+        (s, 1.0)
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("'{s}' is not a valid duration"))?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!("'{s}' must be a finite, non-negative duration"));
+    }
+    Ok(Duration::from_secs_f64(value * scale))
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s
+        .parse()
+        .map_err(|_| format!("'{s}' is not a valid number"))?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err("rate must be a finite number greater than 0".to_string());
+    }
+    Ok(rate)
+}
+
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let interval = parse_duration(s)?;
+    if interval.is_zero() {
+        return Err("interval must be greater than 0".to_string());
+    }
+    Ok(interval)
+}
+
 // Parse into Ok(None) when the topic string is empty. This covers the edge case
-// of TOPICS="". Later this will be filtered out with `remove_none`
-fn parse_topics(topics: &str) -> Result<Option<OwnedKeyExpr>, String> {
+// of TOPICS="". Later this will be filtered out with `remove_none`. Leading
+// `/` shorthand is expanded against --topic-prefix in `main`.
+fn parse_topics(topics: &str) -> Result<Option<String>, String> {
     if topics.is_empty() {
         return Ok(None);
     }
-    let mut topic = topics.to_owned();
-    if topic.starts_with("/") {
-        topic = "rt".to_owned() + &topic;
-    }
-    match OwnedKeyExpr::autocanonize(topic) {
-        Ok(v) => Ok(Some(v)),
-        Err(_) => Err(format!("Could not parse topic: {topics}")),
-    }
+    Ok(Some(topics.to_owned()))
 }
 
 impl From<Args> for Config {
@@ -160,3 +549,114 @@ impl From<Args> for Config {
         config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_time_offset, parse_time_spec, split_paths, Args, TimeSpec};
+    use clap::{CommandFactory, Parser};
+    use std::{
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_mcap_paths() {
+        // Paths on the command line may contain spaces.
+        let matches = Args::command()
+            .try_get_matches_from(["edgefirst-replay", "/tmp/my drive.mcap", "b.mcap"])
+            .unwrap();
+        let args = Args::from_matches(&matches).unwrap();
+        assert_eq!(
+            args.mcap,
+            [PathBuf::from("/tmp/my drive.mcap"), PathBuf::from("b.mcap")]
+        );
+
+        // The environment variable is a space-delimited list.
+        assert_eq!(
+            split_paths(&[PathBuf::from("a.mcap  /data/drive/")]),
+            [PathBuf::from("a.mcap"), PathBuf::from("/data/drive/")]
+        );
+    }
+
+    #[test]
+    fn test_default_topics_follow_prefix() {
+        for (prefix, expected) in [
+            (
+                "rt",
+                [
+                    "rt/replay/control",
+                    "rt/replay/status",
+                    "rt/clock",
+                    "rt/camera/dma",
+                ],
+            ),
+            (
+                "none",
+                ["replay/control", "replay/status", "clock", "camera/dma"],
+            ),
+            (
+                "robot1/rt",
+                [
+                    "robot1/rt/replay/control",
+                    "robot1/rt/replay/status",
+                    "robot1/rt/clock",
+                    "robot1/rt/camera/dma",
+                ],
+            ),
+        ] {
+            let args =
+                Args::try_parse_from(["edgefirst-replay", "a.mcap", "--topic-prefix", prefix])
+                    .unwrap();
+            let topics = [
+                &args.control_topic,
+                &args.status_topic,
+                &args.clock_topic,
+                &args.dma_topic,
+            ]
+            .map(|t| args.topic_prefix.expand(t));
+            assert_eq!(topics, expected, "--topic-prefix {prefix}");
+        }
+    }
+
+    #[test]
+    fn test_parse_time_spec() {
+        assert_eq!(
+            parse_time_spec("90").unwrap(),
+            TimeSpec::Offset(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_time_spec("1500ms").unwrap(),
+            TimeSpec::Offset(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            parse_time_spec("40m").unwrap(),
+            TimeSpec::Offset(Duration::from_secs(2400))
+        );
+        assert_eq!(
+            parse_time_spec("1.5h").unwrap(),
+            TimeSpec::Offset(Duration::from_secs(5400))
+        );
+        assert_eq!(
+            parse_time_spec("@1716400000000000000").unwrap(),
+            TimeSpec::Absolute(1716400000000000000)
+        );
+        assert!(parse_time_spec("-5s").is_err());
+        assert!(parse_time_spec("@12.5").is_err());
+        assert!(parse_time_spec("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_offset() {
+        let offset = parse_time_offset("radar-*.mcap=-250ms").unwrap();
+        assert_eq!(offset.offset, -250_000_000);
+        assert!(offset.matches(Path::new("/data/radar-0001.mcap")));
+        assert!(!offset.matches(Path::new("/data/camera-0001.mcap")));
+
+        let offset = parse_time_offset("/data/lidar.mcap=1.5s").unwrap();
+        assert_eq!(offset.offset, 1_500_000_000);
+        assert!(offset.matches(Path::new("/data/lidar.mcap")));
+
+        assert!(parse_time_offset("radar.mcap").is_err());
+        assert!(parse_time_offset("radar.mcap=soon").is_err());
+    }
+}
@@ -0,0 +1,595 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-source camera streams.
+//!
+//! Every replayed `CompressedVideo` and `CompressedImage` channel gets its
+//! own decoder and hal image publisher, so a recording with several cameras
+//! (`/camera/front/h264`, `/camera/rear/h264`) decodes each stream on its own
+//! and publishes it on its own DMA and image topics.
+//!
+//! The streams to decode are chosen once, before replay starts, from the
+//! channel list (`--dma-source`). `auto` prefers `CompressedVideo` over
+//! `CompressedImage` within each camera, i.e. parent key, so `/camera/h264`
+//! feeds the DMA path and `/camera/jpeg` is only passed through. Every
+//! video channel is decoded, as are the JPEG channels of cameras without
+//! one, and sibling video channels get output topics of their own (see
+//! below). Otherwise exactly the listed channels are decoded.
+//!
+//! Output topics come from `--camera-stream source=dma[,image]` when the
+//! source is listed there. Otherwise a single decoded stream publishes on
+//! `--dma-topic` and `--camera-image-topic`, and with several each stream
+//! publishes next to its source key under the last chunk of those options,
+//! e.g. `rt/camera/front/h264` on `rt/camera/front/dma` and
+//! `rt/camera/front/image`. Streams whose sources share a parent publish
+//! below their source key instead, e.g. `rt/camera/front_h264` on
+//! `rt/camera/front_h264/dma`. The `--camera-info-topic` and
+//! `--camera-jpeg-topic` outputs follow the same rules, and for a
+//! `--camera-stream` source sit next to its DMA topic. Replay refuses to
+//! start when two streams would still publish on the same topic.
+//!
+//! `--camera-image-orientation source=rotation[,flip]` overrides the image
+//! rotation and flip of individual streams, e.g. a rear camera mounted
+//! upside down.
+//!
+//! Every loop and seek restarts each stream's decoders so decoding picks up
+//! cleanly from the first keyframe, and the image publishers release their
+//! imports of the old decoder buffers; `--loop-reset all` also frees the
+//! publishers' destination buffers, as if replay had just started.
+
+use crate::{
+    camera_info::{Calibration, CameraInfoPublisher},
+    image_publish::{HalImagePublisher, ImageOptions, JpegOptions, Orientation},
+    remap::TopicPrefix,
+    video_decode::{CompressedVideoDecoder, JpegStream},
+};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+pub const VIDEO_SCHEMA: &str = "foxglove_msgs/msg/CompressedVideo";
+pub const JPEG_SCHEMA: &str = "sensor_msgs/msg/CompressedImage";
+
+/// Explicit output topics for one camera source, from `--camera-stream`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraOutputs {
+    pub source: String,
+    pub dma_topic: String,
+    pub image_topic: Option<String>,
+}
+
+impl FromStr for CameraOutputs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, outputs) = s
+            .split_once('=')
+            .ok_or_else(|| format!("'{s}' is not of the form source=dma_topic[,image_topic]"))?;
+        let (dma_topic, image_topic) = match outputs.split_once(',') {
+            Some((dma, image)) => (dma, Some(image)),
+            None => (outputs, None),
+        };
+        if source.is_empty() || dma_topic.is_empty() || image_topic == Some("") {
+            return Err(format!("'{s}' has an empty topic"));
+        }
+        Ok(CameraOutputs {
+            source: source.to_owned(),
+            dma_topic: dma_topic.to_owned(),
+            image_topic: image_topic.map(str::to_owned),
+        })
+    }
+}
+
+/// Image rotation and flip for one camera source, from
+/// `--camera-image-orientation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraOrientation {
+    pub source: String,
+    pub orientation: Orientation,
+}
+
+impl FromStr for CameraOrientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, orientation) = s
+            .split_once('=')
+            .filter(|(source, _)| !source.is_empty())
+            .ok_or_else(|| format!("'{s}' is not of the form source=rotation[,flip]"))?;
+        Ok(CameraOrientation {
+            source: source.to_owned(),
+            orientation: orientation.parse()?,
+        })
+    }
+}
+
+/// Camera channels feeding the DMA path, from `--dma-source`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DmaSource {
+    Auto,
+    Topics(Vec<String>),
+}
+
+impl FromStr for DmaSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let topics: Vec<String> = s.split_whitespace().map(str::to_owned).collect();
+        match topics.as_slice() {
+            [] => Ok(DmaSource::Auto),
+            [t] if t == "auto" => Ok(DmaSource::Auto),
+            _ if topics.iter().any(|t| t == "auto") => {
+                Err("--dma-source is either auto or a list of topics".to_owned())
+            }
+            _ => Ok(DmaSource::Topics(topics)),
+        }
+    }
+}
+
+/// A camera channel in the recording.
+#[derive(Clone, Copy)]
+pub struct CameraSource<'a> {
+    /// Recorded MCAP topic.
+    pub topic: &'a str,
+    /// Key the channel itself is published on, after remapping.
+    pub key: &'a str,
+    pub schema: &'a str,
+}
+
+/// Decoding and publishing state of one camera source.
+pub struct CameraStream {
+    pub dma_topic: String,
+    pub video: Option<CompressedVideoDecoder>,
+    pub jpeg: Option<JpegStream>,
+    /// Lives across replay-loop restarts unless `--loop-reset all`; its
+    /// pre-allocated destination ring is otherwise only rebuilt when the
+    /// frame size changes, and its inode-keyed source cache is emptied with
+    /// every reset of the decoders whose buffers it imports. `None` when the
+    /// stream has no image topic.
+    pub hal_publisher: Option<HalImagePublisher>,
+    /// Re-encodes frames to JPEG. `None` when the stream has no JPEG topic.
+    pub jpeg_publisher: Option<HalImagePublisher>,
+    /// `None` when the stream has no camera_info topic or no calibration.
+    pub camera_info: Option<CameraInfoPublisher>,
+}
+
+/// What camera streams drop at the start of every loop, from
+/// `--loop-reset`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LoopReset {
+    /// The decoders, so decoding restarts from a keyframe, and the image
+    /// publishers' source caches of their buffers.
+    #[default]
+    Decoders,
+    /// The decoders and the image publishers' whole state.
+    All,
+}
+
+impl CameraStream {
+    /// Drop the decoders so the next pass restarts cleanly from a keyframe,
+    /// along with the image publishers' imports of their buffers, and with
+    /// [`LoopReset::All`] the image publishers' whole state.
+    pub fn reset(&mut self, reset: LoopReset) {
+        self.video = None;
+        self.jpeg = None;
+        for publisher in self
+            .hal_publisher
+            .iter_mut()
+            .chain(&mut self.jpeg_publisher)
+        {
+            match reset {
+                LoopReset::Decoders => publisher.release_sources(),
+                LoopReset::All => publisher.reset(),
+            }
+        }
+    }
+}
+
+/// Pick the camera channels to decode.
+pub fn select<'a>(
+    channels: Vec<CameraSource<'a>>,
+    selection: &DmaSource,
+    prefix: &TopicPrefix,
+) -> Vec<CameraSource<'a>> {
+    match selection {
+        DmaSource::Topics(topics) => {
+            for t in topics {
+                if !channels
+                    .iter()
+                    .any(|c| prefix.key(c.topic) == prefix.expand(t))
+                {
+                    warn!("DMA source {t} is not a replayed camera topic");
+                }
+            }
+            channels
+                .into_iter()
+                .filter(|c| {
+                    topics
+                        .iter()
+                        .any(|t| prefix.key(c.topic) == prefix.expand(t))
+                })
+                .collect()
+        }
+        DmaSource::Auto => {
+            // The video channel of each camera that has one, by parent key.
+            let mut video: BTreeMap<String, &str> = BTreeMap::new();
+            for c in channels.iter().filter(|c| c.schema == VIDEO_SCHEMA) {
+                video.entry(parent(c.topic, prefix)).or_insert(c.topic);
+            }
+            let mut selected: Vec<_> = channels
+                .iter()
+                .filter(|c| {
+                    if c.schema == VIDEO_SCHEMA {
+                        return true;
+                    }
+                    match video.get(&parent(c.topic, prefix)) {
+                        Some(video) => {
+                            info!(
+                                "Not decoding {}: camera also records video on {video}",
+                                c.topic
+                            );
+                            false
+                        }
+                        None => true,
+                    }
+                })
+                .copied()
+                .collect();
+            selected.sort_by_key(|c| c.topic);
+            selected
+        }
+    }
+}
+
+/// Parent key of a recorded topic, identifying the camera it belongs to.
+fn parent(topic: &str, prefix: &TopicPrefix) -> String {
+    let key = prefix.key(topic);
+    match key.rsplit_once('/') {
+        Some((parent, _)) => parent.to_owned(),
+        None => String::new(),
+    }
+}
+
+/// Resolve the output topics of every camera source and create its stream,
+/// keyed by recorded topic. `configured` and the topics are output keys; an
+/// empty `image_topic` disables image output for streams not configured
+/// explicitly and an empty `jpeg_topic` or `info_topic` disables JPEG or
+/// camera_info output for all of them. `calibrations` is keyed by recorded
+/// topic. Fails when two streams would publish on the same topic.
+#[allow(clippy::too_many_arguments)]
+pub fn streams(
+    sources: &[CameraSource],
+    configured: &[CameraOutputs],
+    orientations: &[CameraOrientation],
+    prefix: &TopicPrefix,
+    dma_topic: &str,
+    image_topic: &str,
+    jpeg_topic: &str,
+    info_topic: &str,
+    image_options: ImageOptions,
+    jpeg_options: JpegOptions,
+    calibrations: &HashMap<String, Calibration>,
+) -> Result<HashMap<String, CameraStream>, String> {
+    let configured_for = |source: &CameraSource| {
+        configured
+            .iter()
+            .find(|c| prefix.expand(&c.source) == prefix.key(source.topic))
+    };
+    // Streams derive their outputs next to their source key, or below it
+    // when another derived stream shares the same parent.
+    let derived: Vec<_> = sources
+        .iter()
+        .filter(|s| configured_for(s).is_none())
+        .collect();
+    let derive = sources.len() > 1;
+
+    let mut streams = HashMap::new();
+    let mut claimed: HashMap<String, &str> = HashMap::new();
+    for source in sources {
+        let shared = derived
+            .iter()
+            .filter(|s| parent_key(s.key) == parent_key(source.key))
+            .count()
+            > 1;
+        let base = match configured_for(source) {
+            Some(c) => Some(parent_key(&c.dma_topic)),
+            None if shared => Some(source.key),
+            None if derive => Some(parent_key(source.key)),
+            None => None,
+        };
+        let optional = |topic: &str| {
+            (!topic.is_empty()).then(|| match base {
+                Some(base) => derive_topic(base, topic),
+                None => topic.to_owned(),
+            })
+        };
+        let (dma, image, jpeg, info) = match configured_for(source) {
+            Some(c) => (
+                c.dma_topic.clone(),
+                c.image_topic.clone(),
+                optional(jpeg_topic),
+                optional(info_topic),
+            ),
+            None => (
+                optional(dma_topic).unwrap_or_default(),
+                optional(image_topic),
+                optional(jpeg_topic),
+                optional(info_topic),
+            ),
+        };
+        let calibration = calibrations.get(source.topic);
+        if info.is_some() && calibration.is_none() {
+            warn!(
+                "No calibration for camera stream {}; not publishing camera_info",
+                source.topic
+            );
+        }
+        let info = info.filter(|_| calibration.is_some());
+        let mut image_options = image_options;
+        if let Some(o) = orientations
+            .iter()
+            .find(|o| prefix.expand(&o.source) == prefix.key(source.topic))
+        {
+            image_options.geometry.orientation = o.orientation;
+        }
+        info!(
+            "Camera stream {}: DMA buffers on {}, images on {}, JPEG on {}, camera_info on {}",
+            source.topic,
+            dma,
+            image.as_deref().unwrap_or("-"),
+            jpeg.as_deref().unwrap_or("-"),
+            info.as_deref().unwrap_or("-")
+        );
+        for topic in [Some(&dma), image.as_ref(), jpeg.as_ref(), info.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            match claimed.insert(topic.clone(), source.topic) {
+                Some(other) if other != source.topic => {
+                    return Err(format!(
+                        "camera streams {other} and {} both publish on {topic}; \
+                         use --camera-stream to separate them",
+                        source.topic
+                    ));
+                }
+                _ => {}
+            }
+        }
+        streams.insert(
+            source.topic.to_owned(),
+            CameraStream {
+                dma_topic: dma,
+                video: None,
+                jpeg: None,
+                hal_publisher: image.map(|t| HalImagePublisher::new(t, image_options)),
+                jpeg_publisher: jpeg.map(|t| {
+                    let options = ImageOptions::jpeg(
+                        image_options.buffers,
+                        image_options.geometry.orientation,
+                        jpeg_options,
+                    );
+                    HalImagePublisher::new(t, options)
+                }),
+                camera_info: info
+                    .zip(calibration)
+                    .map(|(t, c)| CameraInfoPublisher::new(t, c.clone())),
+            },
+        );
+    }
+    Ok(streams)
+}
+
+/// Parent of an output key, empty for a single-chunk key.
+fn parent_key(key: &str) -> &str {
+    key.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Place an output topic under `base`: `base` joined with the last chunk of
+/// `output`.
+fn derive_topic(base: &str, output: &str) -> String {
+    let name = output.rsplit('/').next().unwrap_or(output);
+    match base {
+        "" => name.to_owned(),
+        base => format!("{base}/{name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        derive_topic, select, streams, CameraOrientation, CameraOutputs, CameraSource, DmaSource,
+    };
+    use crate::{
+        image_publish::{ImageOptions, JpegOptions},
+        remap::TopicPrefix,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_select() {
+        let channel = |topic, schema| CameraSource {
+            topic,
+            key: topic,
+            schema,
+        };
+        let channels = || {
+            vec![
+                channel("/camera/front/h264", super::VIDEO_SCHEMA),
+                channel("/camera/front/jpeg", super::JPEG_SCHEMA),
+                channel("/camera/rear/jpeg", super::JPEG_SCHEMA),
+            ]
+        };
+        let topics = |selected: Vec<CameraSource>| -> Vec<String> {
+            selected.iter().map(|c| c.topic.to_owned()).collect()
+        };
+        let prefix = TopicPrefix::default();
+
+        let auto = select(channels(), &"auto".parse().unwrap(), &prefix);
+        assert_eq!(topics(auto), ["/camera/front/h264", "/camera/rear/jpeg"]);
+
+        // Two video channels under one parent are both decoded, and the
+        // JPEG channel next to them is not.
+        let shared = vec![
+            channel("/camera/front_h264", super::VIDEO_SCHEMA),
+            channel("/camera/jpeg", super::JPEG_SCHEMA),
+            channel("/camera/rear_h264", super::VIDEO_SCHEMA),
+        ];
+        let auto = select(shared, &DmaSource::Auto, &prefix);
+        assert_eq!(topics(auto), ["/camera/front_h264", "/camera/rear_h264"]);
+        let explicit: DmaSource = "rt/camera/front/jpeg".parse().unwrap();
+        let selected = select(channels(), &explicit, &prefix);
+        assert_eq!(topics(selected), ["/camera/front/jpeg"]);
+        assert_eq!("".parse::<DmaSource>(), Ok(DmaSource::Auto));
+        assert!("auto /camera/h264".parse::<DmaSource>().is_err());
+    }
+
+    #[test]
+    fn test_derive_topic() {
+        assert_eq!(
+            derive_topic("rt/camera/front", "rt/camera/dma"),
+            "rt/camera/front/dma"
+        );
+        assert_eq!(
+            derive_topic("rt/camera/front_h264", "rt/camera/dma"),
+            "rt/camera/front_h264/dma"
+        );
+        assert_eq!(derive_topic("", "image"), "image");
+    }
+
+    #[test]
+    fn test_stream_topics() {
+        let channel = |topic| CameraSource {
+            topic,
+            key: topic,
+            schema: super::VIDEO_SCHEMA,
+        };
+        let outputs = |sources: &[CameraSource], configured: &[CameraOutputs]| {
+            streams(
+                sources,
+                configured,
+                &[],
+                &TopicPrefix::default(),
+                "rt/camera/dma",
+                "rt/camera/image",
+                "",
+                "",
+                ImageOptions {
+                    buffers: 1,
+                    format: Default::default(),
+                    geometry: Default::default(),
+                    output: Default::default(),
+                },
+                JpegOptions {
+                    quality: 90,
+                    downscale: 1,
+                },
+                &HashMap::new(),
+            )
+            .map(|streams| {
+                let mut topics: Vec<_> = streams
+                    .into_iter()
+                    .map(|(source, s)| {
+                        let image = s.hal_publisher.map(|p| p.topic().to_owned());
+                        (source, s.dma_topic, image.unwrap_or_default())
+                    })
+                    .collect();
+                topics.sort();
+                topics
+            })
+        };
+        let owned = |source: &str, dma: &str, image: &str| {
+            (source.to_owned(), dma.to_owned(), image.to_owned())
+        };
+
+        let single = outputs(&[channel("rt/camera/h264")], &[]).unwrap();
+        assert_eq!(
+            single,
+            [owned("rt/camera/h264", "rt/camera/dma", "rt/camera/image")]
+        );
+
+        let cameras = [
+            channel("rt/camera/front/h264"),
+            channel("rt/camera/rear/h264"),
+        ];
+        assert_eq!(
+            outputs(&cameras, &[]).unwrap(),
+            [
+                owned(
+                    "rt/camera/front/h264",
+                    "rt/camera/front/dma",
+                    "rt/camera/front/image"
+                ),
+                owned(
+                    "rt/camera/rear/h264",
+                    "rt/camera/rear/dma",
+                    "rt/camera/rear/image"
+                ),
+            ]
+        );
+
+        // Sibling video channels publish below their own keys.
+        let siblings = [
+            channel("rt/camera/front_h264"),
+            channel("rt/camera/rear_h264"),
+        ];
+        assert_eq!(
+            outputs(&siblings, &[]).unwrap(),
+            [
+                owned(
+                    "rt/camera/front_h264",
+                    "rt/camera/front_h264/dma",
+                    "rt/camera/front_h264/image"
+                ),
+                owned(
+                    "rt/camera/rear_h264",
+                    "rt/camera/rear_h264/dma",
+                    "rt/camera/rear_h264/image"
+                ),
+            ]
+        );
+
+        // Configuring one sibling leaves the other alone under the parent.
+        let rear: CameraOutputs = "rt/camera/rear_h264=rt/rear/dma,rt/rear/image"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            outputs(&siblings, &[rear]).unwrap(),
+            [
+                owned("rt/camera/front_h264", "rt/camera/dma", "rt/camera/image"),
+                owned("rt/camera/rear_h264", "rt/rear/dma", "rt/rear/image"),
+            ]
+        );
+
+        // Explicit topics that still collide are refused.
+        let clash: Vec<CameraOutputs> = [
+            "rt/camera/front_h264=rt/cam/dma",
+            "rt/camera/rear_h264=rt/cam/dma",
+        ]
+        .iter()
+        .map(|c| c.parse().unwrap())
+        .collect();
+        assert!(outputs(&siblings, &clash).is_err());
+    }
+
+    #[test]
+    fn test_camera_outputs() {
+        let c: CameraOutputs = "/camera/rear/h264=rt/rear/dma,rt/rear/image"
+            .parse()
+            .unwrap();
+        assert_eq!(c.source, "/camera/rear/h264");
+        assert_eq!(c.dma_topic, "rt/rear/dma");
+        assert_eq!(c.image_topic.as_deref(), Some("rt/rear/image"));
+        let c: CameraOutputs = "rt/camera/h264=rt/cam/dma".parse().unwrap();
+        assert_eq!(c.image_topic, None);
+        assert!("rt/camera/h264".parse::<CameraOutputs>().is_err());
+        assert!("rt/camera/h264=".parse::<CameraOutputs>().is_err());
+
+        let o: CameraOrientation = "/camera/rear/h264=180,horizontal".parse().unwrap();
+        assert_eq!(o.source, "/camera/rear/h264");
+        assert_eq!(o.orientation, "180,horizontal".parse().unwrap());
+        assert!("/camera/rear/h264".parse::<CameraOrientation>().is_err());
+        assert!("=90".parse::<CameraOrientation>().is_err());
+    }
+}
@@ -0,0 +1,596 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Camera calibration published as `sensor_msgs/CameraInfo`.
+//!
+//! Intrinsics come from `--camera-calibration` files or, without one, from
+//! the recordings themselves: an attachment holding a calibration file, or a
+//! metadata record named `camera_info` or `calibration` whose fields are the
+//! calibration keys. Files use the ROS `camera_calibration` layout
+//! (`image_width`, `camera_matrix`, ...) or the `CameraInfo` field names
+//! (`width`, `k`, ...), in YAML or JSON.
+//!
+//! Every decoded frame is followed by a `CameraInfo` carrying the frame's
+//! stamp and frame_id. The intrinsics are scaled from the calibrated size to
+//! the decoded frame and then follow the RGBA image's ROI, resize,
+//! letterbox or crop, flip and rotation, so they describe the published
+//! image. On a rotated image the optical frame turns with it: K and P keep
+//! their usual form with the focal lengths swapped on a quarter turn.
+
+use crate::{
+    camera::CameraSource,
+    image_publish::{ImageFlip, ImageRotation, Layout, Orientation},
+    playlist::Recording,
+    remap::TopicPrefix,
+};
+use edgefirst_schemas::{builtin_interfaces::Time, sensor_msgs::CameraInfo};
+use log::{debug, info, warn};
+use mcap::records::Record;
+use serde::Deserialize;
+use serde_yaml_ng::{Mapping, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+use zenoh::{
+    bytes::{Encoding, ZBytes},
+    Session, Wait,
+};
+
+const CAMERA_INFO_SCHEMA: &str = "sensor_msgs/msg/CameraInfo";
+/// Metadata record names holding a calibration.
+const METADATA_NAMES: [&str; 2] = ["camera_info", "calibration"];
+
+/// A calibration file from `--camera-calibration`, for one camera source or,
+/// without `source=`, for every camera without its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalibrationFile {
+    pub source: Option<String>,
+    pub path: PathBuf,
+}
+
+impl FromStr for CalibrationFile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, path) = match s.split_once('=') {
+            Some((source, path)) => (Some(source.to_owned()), path),
+            None => (None, s),
+        };
+        if path.is_empty() || source.as_deref() == Some("") {
+            return Err(format!("'{s}' is not of the form [source=]path"));
+        }
+        Ok(CalibrationFile {
+            source,
+            path: path.into(),
+        })
+    }
+}
+
+/// A matrix either as a flat list or as `{rows, cols, data}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Matrix {
+    Data { data: Vec<f64> },
+    Flat(Vec<f64>),
+}
+
+impl Matrix {
+    fn into_vec(self) -> Vec<f64> {
+        match self {
+            Matrix::Data { data } => data,
+            Matrix::Flat(data) => data,
+        }
+    }
+
+    fn into_array<const N: usize>(self, name: &str) -> Result<[f64; N], String> {
+        let data = self.into_vec();
+        let len = data.len();
+        data.try_into()
+            .map_err(|_| format!("{name} has {len} values, expected {N}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct RawCalibration {
+    #[serde(default)]
+    camera_name: String,
+    #[serde(alias = "width")]
+    image_width: u32,
+    #[serde(alias = "height")]
+    image_height: u32,
+    #[serde(default)]
+    distortion_model: Option<String>,
+    #[serde(alias = "d", default)]
+    distortion_coefficients: Option<Matrix>,
+    #[serde(alias = "k")]
+    camera_matrix: Matrix,
+    #[serde(alias = "r", default)]
+    rectification_matrix: Option<Matrix>,
+    #[serde(alias = "p", default)]
+    projection_matrix: Option<Matrix>,
+}
+
+/// Intrinsics of one camera at the resolution it was calibrated at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub camera_name: String,
+    pub width: u32,
+    pub height: u32,
+    pub distortion_model: String,
+    pub d: Vec<f64>,
+    pub k: [f64; 9],
+    pub r: [f64; 9],
+    pub p: [f64; 12],
+}
+
+impl TryFrom<RawCalibration> for Calibration {
+    type Error = String;
+
+    fn try_from(raw: RawCalibration) -> Result<Self, Self::Error> {
+        if raw.image_width == 0 || raw.image_height == 0 {
+            return Err("calibration has no image size".to_owned());
+        }
+        let k: [f64; 9] = raw.camera_matrix.into_array("camera_matrix")?;
+        let r = match raw.rectification_matrix {
+            Some(r) => r.into_array("rectification_matrix")?,
+            None => [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        };
+        // Monocular cameras project with K itself.
+        let p = match raw.projection_matrix {
+            Some(p) => p.into_array("projection_matrix")?,
+            None => [
+                k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0,
+            ],
+        };
+        let d = raw
+            .distortion_coefficients
+            .map(Matrix::into_vec)
+            .unwrap_or_default();
+        let distortion_model = match raw.distortion_model {
+            Some(m) => m,
+            None if d.len() > 5 => "rational_polynomial".to_owned(),
+            None => "plumb_bob".to_owned(),
+        };
+        Ok(Calibration {
+            camera_name: raw.camera_name,
+            width: raw.image_width,
+            height: raw.image_height,
+            distortion_model,
+            d,
+            k,
+            r,
+            p,
+        })
+    }
+}
+
+impl Calibration {
+    /// Parse a calibration file in YAML or JSON.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let raw: RawCalibration = serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?;
+        raw.try_into()
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path:?}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path:?}: {e}"))
+    }
+
+    /// Build a calibration from MCAP metadata, whose values are strings: each
+    /// is read as YAML so `"640"` and `"[1, 0, 0]"` become numbers and lists.
+    fn from_metadata(metadata: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut mapping = Mapping::new();
+        for (key, value) in metadata {
+            let value = serde_yaml_ng::from_str(value).unwrap_or(Value::String(value.clone()));
+            mapping.insert(Value::String(key.clone()), value);
+        }
+        let raw: RawCalibration =
+            serde_yaml_ng::from_value(Value::Mapping(mapping)).map_err(|e| e.to_string())?;
+        raw.try_into()
+    }
+
+    /// K and P of a `width`x`height` frame laid out as `layout`: scaled from
+    /// the calibrated size to the frame, then mapping the layout's source
+    /// rectangle onto its destination rectangle, then flipped and rotated.
+    fn project(&self, width: u32, height: u32, layout: &Layout) -> ([f64; 9], [f64; 12]) {
+        let src = layout.src;
+        // Scale into the image as it is before the flip and rotation.
+        let (dst, turned_width, turned_height) =
+            layout
+                .orientation
+                .unturn(layout.dst, layout.width as usize, layout.height as usize);
+        let zx = dst.width as f64 / src.width as f64;
+        let zy = dst.height as f64 / src.height as f64;
+        let sx = width as f64 / self.width as f64 * zx;
+        let sy = height as f64 / self.height as f64 * zy;
+        // Principal points move with the source origin and destination offset.
+        let cx = |c: f64| {
+            (c * width as f64 / self.width as f64 - src.left as f64) * zx + dst.left as f64
+        };
+        let cy = |c: f64| {
+            (c * height as f64 / self.height as f64 - src.top as f64) * zy + dst.top as f64
+        };
+
+        let mut k = self.k;
+        let mut p = self.p;
+        k[0] *= sx;
+        k[1] *= sx;
+        k[2] = cx(k[2]);
+        k[4] *= sy;
+        k[5] = cy(k[5]);
+        p[0] *= sx;
+        p[1] *= sx;
+        p[2] = cx(p[2]);
+        p[3] *= sx;
+        p[5] *= sy;
+        p[6] = cy(p[6]);
+        p[7] *= sy;
+
+        let (w, h) = (turned_width as f64, turned_height as f64);
+        let p = turn(p, w, h, layout.orientation);
+        let q = turn(
+            [
+                k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0,
+            ],
+            w,
+            h,
+            layout.orientation,
+        );
+        let k = [q[0], q[1], q[2], q[4], q[5], q[6], q[8], q[9], q[10]];
+        (k, p)
+    }
+}
+
+/// P of a `width`x`height` image after `orientation`'s flip and rotation.
+/// The skew term, zero in real calibrations, is only mirrored.
+fn turn(p: [f64; 12], width: f64, height: f64, orientation: Orientation) -> [f64; 12] {
+    let [mut fx, mut skew, mut cx, mut tx, _, mut fy, mut cy, mut ty, ..] = p;
+    match orientation.flip {
+        ImageFlip::None => {}
+        ImageFlip::Horizontal => (cx, tx, skew) = (width - cx, -tx, -skew),
+        ImageFlip::Vertical => (cy, ty, skew) = (height - cy, -ty, -skew),
+    }
+    (fx, fy, cx, cy, tx, ty) = match orientation.rotation {
+        ImageRotation::None => (fx, fy, cx, cy, tx, ty),
+        ImageRotation::Clockwise90 => (fy, fx, height - cy, cx, -ty, tx),
+        ImageRotation::Rotate180 => (fx, fy, width - cx, height - cy, -tx, -ty),
+        ImageRotation::CounterClockwise90 => (fy, fx, cy, width - cx, ty, -tx),
+    };
+    let mut out = p;
+    (out[0], out[1], out[2], out[3]) = (fx, skew, cx, tx);
+    (out[5], out[6], out[7]) = (fy, cy, ty);
+    out
+}
+
+/// Calibration of every camera source that has one, keyed by recorded topic:
+/// its own `--camera-calibration` file, the file given without a source, or
+/// a calibration stored in the recordings.
+pub fn resolve(
+    sources: &[CameraSource],
+    files: &[CalibrationFile],
+    recordings: &[Recording],
+    prefix: &TopicPrefix,
+) -> Result<HashMap<String, Calibration>, String> {
+    let mut recorded: Option<Vec<_>> = None;
+    let mut calibrations = HashMap::new();
+    for source in sources {
+        let key = prefix.key(source.topic);
+        let file = files
+            .iter()
+            .find(|f| f.source.as_ref().is_some_and(|s| prefix.expand(s) == key))
+            .or_else(|| files.iter().find(|f| f.source.is_none()));
+        let calibration = match file {
+            Some(f) => Calibration::load(&f.path)?,
+            None => {
+                let recorded = recorded
+                    .get_or_insert_with(|| recordings.iter().flat_map(from_recording).collect());
+                match match_recorded(recorded, &key) {
+                    Some(c) => c.clone(),
+                    None => continue,
+                }
+            }
+        };
+        info!(
+            "Calibration for {}: {}x{} {}",
+            source.topic, calibration.width, calibration.height, calibration.distortion_model
+        );
+        calibrations.insert(source.topic.to_owned(), calibration);
+    }
+    for f in files {
+        if let Some(s) = &f.source {
+            if !sources
+                .iter()
+                .any(|c| prefix.key(c.topic) == prefix.expand(s))
+            {
+                warn!(
+                    "Calibration {:?} is for {s}, not a decoded camera topic",
+                    f.path
+                );
+            }
+        }
+    }
+    Ok(calibrations)
+}
+
+/// Calibrations stored in a recording's attachments and metadata records,
+/// with the attachment or record name.
+fn from_recording(recording: &Recording) -> Vec<(String, Calibration)> {
+    let mapped: &[u8] = &recording.mapped;
+    let mut found = Vec::new();
+    let mut add = |name: String, calibration: Result<Calibration, String>| match calibration {
+        Ok(c) => {
+            debug!("Found calibration {name} in {:?}", recording.path);
+            found.push((name, c));
+        }
+        Err(e) => warn!("Ignoring calibration {name} in {:?}: {e}", recording.path),
+    };
+
+    match &recording.summary {
+        Some(summary)
+            if !summary.attachment_indexes.is_empty() || !summary.metadata_indexes.is_empty() =>
+        {
+            for index in &summary.attachment_indexes {
+                if !is_calibration_attachment(&index.name, &index.media_type) {
+                    continue;
+                }
+                let calibration = mcap::read::attachment(mapped, index)
+                    .map_err(|e| e.to_string())
+                    .and_then(|a| parse_bytes(&a.data));
+                add(index.name.clone(), calibration);
+            }
+            for index in &summary.metadata_indexes {
+                if !METADATA_NAMES.contains(&index.name.as_str()) {
+                    continue;
+                }
+                let calibration = mcap::read::metadata(mapped, index)
+                    .map_err(|e| e.to_string())
+                    .and_then(|m| Calibration::from_metadata(&m.metadata));
+                add(index.name.clone(), calibration);
+            }
+        }
+        _ => {
+            let Ok(reader) = mcap::read::LinearReader::new_with_options(
+                mapped,
+                mcap::read::Options::IgnoreEndMagic.into(),
+            ) else {
+                return found;
+            };
+            for record in reader {
+                match record {
+                    Ok(Record::Attachment { header, data, .. })
+                        if is_calibration_attachment(&header.name, &header.media_type) =>
+                    {
+                        add(header.name, parse_bytes(&data));
+                    }
+                    Ok(Record::Metadata(m)) if METADATA_NAMES.contains(&m.name.as_str()) => {
+                        add(m.name, Calibration::from_metadata(&m.metadata));
+                    }
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+    found
+}
+
+fn is_calibration_attachment(name: &str, media_type: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let text = [".yaml", ".yml", ".json"].iter().any(|e| name.ends_with(e))
+        || media_type.contains("yaml")
+        || media_type.contains("json");
+    text && (name.contains("calib") || name.contains("camera_info"))
+}
+
+fn parse_bytes(data: &[u8]) -> Result<Calibration, String> {
+    let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+    Calibration::parse(text)
+}
+
+/// Pick the recorded calibration for a camera: the only one there is, or the
+/// one whose name or camera_name mentions the camera, i.e. the last chunk of
+/// the source's parent key (`front` for `/camera/front/h264`).
+fn match_recorded<'a>(
+    recorded: &'a [(String, Calibration)],
+    source_key: &str,
+) -> Option<&'a Calibration> {
+    if let [(_, only)] = recorded {
+        return Some(only);
+    }
+    let camera = source_key.rsplit_once('/')?.0.rsplit('/').next()?;
+    if camera.is_empty() {
+        return None;
+    }
+    recorded
+        .iter()
+        .find(|(name, c)| name.contains(camera) || c.camera_name.contains(camera))
+        .map(|(_, c)| c)
+}
+
+/// Publishes the `CameraInfo` of one camera stream.
+pub struct CameraInfoPublisher {
+    topic: String,
+    calibration: Calibration,
+    cdr_scratch: Vec<u8>,
+}
+
+impl CameraInfoPublisher {
+    pub fn new(topic: String, calibration: Calibration) -> Self {
+        Self {
+            topic,
+            calibration,
+            cdr_scratch: Vec::new(),
+        }
+    }
+
+    /// Publish the calibration for a `width`x`height` frame published as
+    /// `layout`.
+    pub fn publish(
+        &mut self,
+        stamp: Time,
+        frame_id: &str,
+        width: u32,
+        height: u32,
+        layout: &Layout,
+        session: &Session,
+    ) -> Result<(), Box<dyn Error>> {
+        let c = &self.calibration;
+        let (k, p) = c.project(width, height, layout);
+        CameraInfo::builder()
+            .stamp(stamp)
+            .frame_id(frame_id)
+            .height(layout.height)
+            .width(layout.width)
+            .distortion_model(c.distortion_model.as_str())
+            .d(&c.d)
+            .k(k)
+            .r(c.r)
+            .p(p)
+            .encode_into_vec(&mut self.cdr_scratch)?;
+        let enc = Encoding::APPLICATION_CDR.with_schema(CAMERA_INFO_SCHEMA);
+        session
+            .put(&self.topic, ZBytes::from(self.cdr_scratch.as_slice()))
+            .encoding(enc)
+            .wait()
+            .map_err(|e| format!("zenoh put on {} failed: {e:?}", self.topic))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_recorded, Calibration, CalibrationFile};
+    use crate::image_publish::{ImageFit, ImageGeometry, Layout};
+    use std::collections::BTreeMap;
+
+    const ROS_YAML: &str = "\
+image_width: 1920
+image_height: 1080
+camera_name: front_camera
+camera_matrix:
+  rows: 3
+  cols: 3
+  data: [1000.0, 0.0, 960.0, 0.0, 1000.0, 540.0, 0.0, 0.0, 1.0]
+distortion_model: plumb_bob
+distortion_coefficients:
+  rows: 1
+  cols: 5
+  data: [-0.1, 0.01, 0.0, 0.0, 0.0]
+";
+
+    #[test]
+    fn test_parse() {
+        let c = Calibration::parse(ROS_YAML).unwrap();
+        assert_eq!((c.width, c.height), (1920, 1080));
+        assert_eq!(c.camera_name, "front_camera");
+        assert_eq!(c.d.len(), 5);
+        assert_eq!(c.r[4], 1.0);
+        assert_eq!(&c.p[..4], &[1000.0, 0.0, 960.0, 0.0]);
+
+        let json = r#"{"width": 640, "height": 480, "k": [500, 0, 320, 0, 500, 240, 0, 0, 1],
+                       "d": [0, 0, 0, 0, 0, 0, 0, 0]}"#;
+        let c = Calibration::parse(json).unwrap();
+        assert_eq!(c.k[2], 320.0);
+        assert_eq!(c.distortion_model, "rational_polynomial");
+        assert!(Calibration::parse("image_width: 640\nimage_height: 480\nk: [1, 2]").is_err());
+
+        let metadata: BTreeMap<String, String> = [
+            ("width", "640"),
+            ("height", "480"),
+            ("k", "[500, 0, 320, 0, 500, 240, 0, 0, 1]"),
+            ("distortion_model", "equidistant"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        let c = Calibration::from_metadata(&metadata).unwrap();
+        assert_eq!(c.distortion_model, "equidistant");
+        assert_eq!(c.k[5], 240.0);
+    }
+
+    #[test]
+    fn test_project() {
+        let c = Calibration::parse(ROS_YAML).unwrap();
+        let (k, p) = c.project(960, 540, &Layout::full(960, 540));
+        assert_eq!(k, [500.0, 0.0, 480.0, 0.0, 500.0, 270.0, 0.0, 0.0, 1.0]);
+        assert_eq!(&p[..8], &[500.0, 0.0, 480.0, 0.0, 0.0, 500.0, 270.0, 0.0]);
+        assert_eq!(c.project(1920, 1080, &Layout::full(1920, 1080)).0, c.k);
+
+        // 1920x1080 letterboxed into 640x640: scale 1/3, 140 rows of bars.
+        let letterbox = ImageGeometry {
+            size: Some("640x640".parse().unwrap()),
+            fit: ImageFit::Letterbox,
+            ..Default::default()
+        };
+        let layout = letterbox.layout(1920, 1080).unwrap();
+        let (k, _) = c.project(1920, 1080, &layout);
+        assert!((k[0] - 1000.0 / 3.0).abs() < 1e-9);
+        assert!((k[2] - 320.0).abs() < 1e-9);
+        assert!((k[5] - 320.0).abs() < 1e-9);
+
+        // A 960x540 ROI at the origin, unscaled: the principal point is the
+        // bottom-right corner of the region.
+        let roi = ImageGeometry {
+            roi: Some("0,0,960,540".parse().unwrap()),
+            ..Default::default()
+        };
+        let (k, _) = c.project(1920, 1080, &roi.layout(1920, 1080).unwrap());
+        assert_eq!(&k[..6], &[1000.0, 0.0, 960.0, 0.0, 1000.0, 540.0]);
+
+        // Rotated a quarter turn clockwise: the principal point 480,270 of
+        // the 960x540 region lands at 270,480 of the 540x960 image.
+        let region = Calibration {
+            k: [1000.0, 0.0, 480.0, 0.0, 900.0, 270.0, 0.0, 0.0, 1.0],
+            ..c.clone()
+        };
+        let rotated = ImageGeometry {
+            roi: Some("0,0,960,540".parse().unwrap()),
+            orientation: "90".parse().unwrap(),
+            ..Default::default()
+        };
+        let layout = rotated.layout(1920, 1080).unwrap();
+        let (k, _) = region.project(1920, 1080, &layout);
+        assert_eq!(&k[..6], &[900.0, 0.0, 270.0, 0.0, 1000.0, 480.0]);
+        // Mirrored: an off-centre principal point moves to the other side, a
+        // centred one stays.
+        let mirrored = ImageGeometry {
+            orientation: "0,horizontal".parse().unwrap(),
+            ..Default::default()
+        };
+        let (k, p) = region.project(1920, 1080, &mirrored.layout(1920, 1080).unwrap());
+        assert_eq!((k[2], p[2]), (1440.0, 960.0));
+    }
+
+    #[test]
+    fn test_match_recorded() {
+        let c = Calibration::parse(ROS_YAML).unwrap();
+        let rear = Calibration {
+            camera_name: String::new(),
+            ..c.clone()
+        };
+        let one = [("calibration".to_owned(), c.clone())];
+        assert!(match_recorded(&one, "rt/camera/rear/h264").is_some());
+        let two = [
+            ("calibration".to_owned(), c.clone()),
+            ("rear_calibration.yaml".to_owned(), rear),
+        ];
+        let front = match_recorded(&two, "rt/camera/front/h264").unwrap();
+        assert_eq!(front.camera_name, "front_camera");
+        let rear = match_recorded(&two, "rt/camera/rear/h264").unwrap();
+        assert_eq!(rear.camera_name, "");
+        assert!(match_recorded(&two, "rt/camera/left/h264").is_none());
+
+        let f: CalibrationFile = "/camera/front/h264=front.yaml".parse().unwrap();
+        assert_eq!(f.source.as_deref(), Some("/camera/front/h264"));
+        assert_eq!(f.path.to_str(), Some("front.yaml"));
+        assert_eq!(
+            "calib.json".parse::<CalibrationFile>().unwrap().source,
+            None
+        );
+        assert!("=calib.json".parse::<CalibrationFile>().is_err());
+    }
+}
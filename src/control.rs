@@ -0,0 +1,256 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Zenoh queryable for runtime playback control.
+//!
+//! Commands are sent as a JSON payload, e.g. `{"command": "seek", "time":
+//! "40m"}`, or as selector parameters, e.g.
+//! `rt/replay/control?command=speed;speed=2.0`. Every reply carries the
+//! resulting playback state as JSON.
+//!
+//! | Command  | Arguments                                 |
+//! |----------|-------------------------------------------|
+//! | `status` | -                                         |
+//! | `pause`  | -                                         |
+//! | `resume` | -                                         |
+//! | `step`   | -                                         |
+//! | `seek`   | `time`: `--start` syntax (`40m`, `@<ns>`) |
+//! | `speed`  | `speed`: multiplier greater than 0        |
+
+use crate::{
+    args::parse_time_spec,
+    playback::{Playback, PlaybackStatus},
+    source::TimeRange,
+};
+use log::{info, warn};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use zenoh::{
+    query::{Parameters, Query, Queryable},
+    Session, Wait,
+};
+
+/// Declare the control queryable on `topic`.
+///
+/// `file_start` anchors seek offsets and seeks are clamped to `range`. The
+/// queryable stays declared for as long as the returned handle is alive.
+pub fn declare(
+    session: &Session,
+    topic: &str,
+    playback: Arc<Playback>,
+    file_start: u64,
+    range: TimeRange,
+) -> Result<Queryable<()>, zenoh::Error> {
+    info!("Declaring playback control queryable on {topic}");
+    session
+        .declare_queryable(topic.to_owned())
+        .callback(move |query| {
+            let reply = request(&query).and_then(|req| handle(&req, &playback, file_start, range));
+            let result = match reply {
+                Ok(status) => query
+                    .reply(query.key_expr().clone(), status_json(&status).to_string())
+                    .wait(),
+                Err(e) => {
+                    warn!("Rejected playback control request: {e}");
+                    query.reply_err(json!({ "error": e }).to_string()).wait()
+                }
+            };
+            if let Err(e) = result {
+                warn!("Could not reply to playback control request: {e:?}");
+            }
+        })
+        .wait()
+}
+
+/// Collect the request from the JSON payload or, failing that, from the
+/// selector parameters.
+fn request(query: &Query) -> Result<Map<String, Value>, String> {
+    let text = match query.payload() {
+        Some(payload) => payload
+            .try_to_string()
+            .map_err(|e| format!("payload is not UTF-8: {e}"))?
+            .into_owned(),
+        None => String::new(),
+    };
+    parse_request(&text, query.parameters())
+}
+
+fn parse_request(payload: &str, parameters: &Parameters) -> Result<Map<String, Value>, String> {
+    if !payload.trim().is_empty() {
+        return match serde_json::from_str(payload) {
+            Ok(Value::Object(map)) => Ok(map),
+            Ok(_) => Err("payload must be a JSON object".to_string()),
+            Err(e) => Err(format!("payload is not valid JSON: {e}")),
+        };
+    }
+    Ok(parameters
+        .iter()
+        .map(|(k, v)| (k.to_owned(), Value::String(v.to_owned())))
+        .collect())
+}
+
+fn handle(
+    req: &Map<String, Value>,
+    playback: &Playback,
+    file_start: u64,
+    range: TimeRange,
+) -> Result<PlaybackStatus, String> {
+    let command = req
+        .get("command")
+        .and_then(Value::as_str)
+        .unwrap_or("status");
+    let status = match command {
+        "status" => playback.status(),
+        "pause" => playback.pause(),
+        "resume" => playback.resume(),
+        "step" => playback.step(),
+        "seek" => {
+            let time = match req.get("time") {
+                Some(Value::String(s)) => parse_time_spec(s)?,
+                Some(Value::Number(n)) => parse_time_spec(&n.to_string())?,
+                _ => return Err("seek requires a 'time' argument".to_string()),
+            };
+            let target = time
+                .resolve(file_start)
+                .clamp(range.start, range.end.saturating_sub(1));
+            playback.seek(target)
+        }
+        "speed" => {
+            let speed = match req.get("speed") {
+                Some(Value::Number(n)) => n.as_f64(),
+                Some(Value::String(s)) => s.parse().ok(),
+                _ => None,
+            }
+            .ok_or("speed requires a numeric 'speed' argument")?;
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err("speed must be a finite number greater than 0".to_string());
+            }
+            if playback.status().afap {
+                return Err("speed has no effect when replaying as fast as possible".to_string());
+            }
+            playback.set_speed(speed)
+        }
+        other => return Err(format!("unknown command '{other}'")),
+    };
+    info!("Playback control '{command}': {status:?}");
+    Ok(status)
+}
+
+fn status_json(status: &PlaybackStatus) -> Value {
+    json!({
+        "state": if status.paused { "paused" } else { "playing" },
+        "speed": status.speed,
+        "afap": status.afap,
+        "log_time": status.log_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle, parse_request};
+    use crate::{
+        playback::{Playback, Wake},
+        source::TimeRange,
+    };
+    use serde_json::{Map, Value};
+    use std::sync::atomic::AtomicBool;
+    use zenoh::query::Parameters;
+
+    const SECOND: u64 = 1_000_000_000;
+    const RANGE: TimeRange = TimeRange {
+        start: 10 * SECOND,
+        end: 20 * SECOND,
+    };
+
+    fn json(payload: &str) -> Map<String, Value> {
+        parse_request(payload, &Parameters::empty()).unwrap()
+    }
+
+    fn selector(parameters: &str) -> Map<String, Value> {
+        parse_request("", &Parameters::from(parameters)).unwrap()
+    }
+
+    #[test]
+    fn test_seek_clamped_to_range() {
+        let playback = Playback::new(1.0, false);
+        let run = AtomicBool::new(true);
+        let seek = |req| {
+            handle(&req, &playback, 5 * SECOND, RANGE).unwrap();
+            playback.wait_until(u64::MAX, &run)
+        };
+        assert_eq!(
+            seek(json(r#"{"command": "seek", "time": "7s"}"#)),
+            Wake::Seek(12 * SECOND)
+        );
+        assert_eq!(
+            seek(selector("command=seek;time=1m")),
+            Wake::Seek(20 * SECOND - 1)
+        );
+        assert_eq!(
+            seek(json(r#"{"command": "seek", "time": "@0"}"#)),
+            Wake::Seek(10 * SECOND)
+        );
+        assert!(handle(&json(r#"{"command": "seek"}"#), &playback, 0, RANGE).is_err());
+    }
+
+    #[test]
+    fn test_speed() {
+        let playback = Playback::new(1.0, false);
+        let status = handle(&selector("command=speed;speed=2.5"), &playback, 0, RANGE).unwrap();
+        assert_eq!(status.speed, 2.5);
+        let status = handle(
+            &json(r#"{"command": "speed", "speed": 0.5}"#),
+            &playback,
+            0,
+            RANGE,
+        );
+        assert_eq!(status.unwrap().speed, 0.5);
+
+        for speed in ["0", "-1", "inf", "NaN", "fast"] {
+            let req = selector(&format!("command=speed;speed={speed}"));
+            assert!(handle(&req, &playback, 0, RANGE).is_err(), "speed {speed}");
+        }
+        assert!(handle(
+            &json(r#"{"command": "speed", "speed": -2}"#),
+            &playback,
+            0,
+            RANGE
+        )
+        .is_err());
+        assert_eq!(playback.status().speed, 0.5);
+
+        let afap = Playback::new(1.0, true);
+        assert!(handle(&selector("command=speed;speed=2"), &afap, 0, RANGE).is_err());
+    }
+
+    #[test]
+    fn test_step_while_paused() {
+        let playback = Playback::new(1.0, false);
+        playback.anchor(RANGE.start);
+        let run = AtomicBool::new(true);
+        let status = handle(&json(r#"{"command": "pause"}"#), &playback, 0, RANGE).unwrap();
+        assert!(status.paused);
+
+        // A message far in the future is released by a single step, and the
+        // clock stays paused.
+        let status = handle(&selector("command=step"), &playback, 0, RANGE).unwrap();
+        assert!(status.paused);
+        assert_eq!(playback.wait_until(RANGE.end, &run), Wake::Publish);
+        assert_eq!(playback.status().position, RANGE.end);
+        assert!(playback.status().paused);
+
+        let status = handle(&selector("command=resume"), &playback, 0, RANGE).unwrap();
+        assert!(!status.paused);
+    }
+
+    #[test]
+    fn test_requests() {
+        let playback = Playback::new(1.0, false);
+        let status = handle(&selector(""), &playback, 0, RANGE).unwrap();
+        assert!(!status.paused);
+        assert!(handle(&json(r#"{"command": "rewind"}"#), &playback, 0, RANGE).is_err());
+        assert!(handle(&selector("command=rewind"), &playback, 0, RANGE).is_err());
+        assert!(parse_request("[1, 2]", &Parameters::empty()).is_err());
+        assert!(parse_request("{", &Parameters::empty()).is_err());
+    }
+}
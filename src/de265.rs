@@ -0,0 +1,234 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Software H.265/HEVC decoding through libde265.
+//!
+//! Like libvideostream, the library is opened at runtime when the first
+//! HEVC stream needs a software decoder, so hosts without it still replay
+//! everything else. Only 8-bit 4:2:0 streams are supported, which covers
+//! what the cameras record.
+
+use libloading::Library;
+use openh264::formats::YUVSource;
+use std::{
+    error::Error,
+    ffi::{c_char, c_int, c_void, CStr},
+    ptr::{self, NonNull},
+    slice,
+};
+
+const LIBRARY: &str = "libde265.so.0";
+
+/// `DE265_ERROR_WAITING_FOR_INPUT_DATA`: everything pushed so far has been
+/// decoded.
+const WAITING_FOR_INPUT_DATA: c_int = 13;
+/// `de265_chroma_420`.
+const CHROMA_420: c_int = 1;
+
+type Context = c_void;
+type Image = c_void;
+
+/// Entry points resolved from libde265. The library is kept loaded for as
+/// long as the function pointers are in use.
+struct Api {
+    new_decoder: unsafe extern "C" fn() -> *mut Context,
+    free_decoder: unsafe extern "C" fn(*mut Context) -> c_int,
+    push_data: unsafe extern "C" fn(*mut Context, *const c_void, c_int, i64, *mut c_void) -> c_int,
+    push_end_of_frame: unsafe extern "C" fn(*mut Context),
+    decode: unsafe extern "C" fn(*mut Context, *mut c_int) -> c_int,
+    peek_next_picture: unsafe extern "C" fn(*mut Context) -> *const Image,
+    release_next_picture: unsafe extern "C" fn(*mut Context),
+    get_image_width: unsafe extern "C" fn(*const Image, c_int) -> c_int,
+    get_image_height: unsafe extern "C" fn(*const Image, c_int) -> c_int,
+    get_chroma_format: unsafe extern "C" fn(*const Image) -> c_int,
+    get_bits_per_pixel: unsafe extern "C" fn(*const Image, c_int) -> c_int,
+    get_image_plane: unsafe extern "C" fn(*const Image, c_int, *mut c_int) -> *const u8,
+    get_error_text: unsafe extern "C" fn(c_int) -> *const c_char,
+    is_ok: unsafe extern "C" fn(c_int) -> c_int,
+    _library: Library,
+}
+
+impl Api {
+    fn load() -> Result<Self, Box<dyn Error>> {
+        // SAFETY: the symbols are resolved with the signatures of the
+        // libde265 1.0 C API and the library outlives them in `_library`.
+        unsafe {
+            let library = Library::new(LIBRARY)?;
+            Ok(Api {
+                new_decoder: *library.get(b"de265_new_decoder\0")?,
+                free_decoder: *library.get(b"de265_free_decoder\0")?,
+                push_data: *library.get(b"de265_push_data\0")?,
+                push_end_of_frame: *library.get(b"de265_push_end_of_frame\0")?,
+                decode: *library.get(b"de265_decode\0")?,
+                peek_next_picture: *library.get(b"de265_peek_next_picture\0")?,
+                release_next_picture: *library.get(b"de265_release_next_picture\0")?,
+                get_image_width: *library.get(b"de265_get_image_width\0")?,
+                get_image_height: *library.get(b"de265_get_image_height\0")?,
+                get_chroma_format: *library.get(b"de265_get_chroma_format\0")?,
+                get_bits_per_pixel: *library.get(b"de265_get_bits_per_pixel\0")?,
+                get_image_plane: *library.get(b"de265_get_image_plane\0")?,
+                get_error_text: *library.get(b"de265_get_error_text\0")?,
+                is_ok: *library.get(b"de265_isOK\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    fn error(&self, code: c_int) -> String {
+        // SAFETY: libde265 returns a static string for every error code.
+        let text = unsafe { CStr::from_ptr((self.get_error_text)(code)) };
+        format!("libde265: {}", text.to_string_lossy())
+    }
+}
+
+/// A libde265 decoder context fed one access unit at a time.
+pub struct Decoder {
+    api: Api,
+    context: NonNull<Context>,
+}
+
+// SAFETY: a libde265 context has no thread affinity; it only needs calls to
+// be serialised, which `&mut self` on `decode` already guarantees. The
+// decoder moves to its stream's decode thread and stays there.
+unsafe impl Send for Decoder {}
+
+impl Decoder {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let api = Api::load()?;
+        // SAFETY: plain constructor, checked for null below.
+        let context = NonNull::new(unsafe { (api.new_decoder)() })
+            .ok_or("libde265: could not allocate decoder")?;
+        Ok(Decoder { api, context })
+    }
+
+    /// Decode one access unit in Annex B byte-stream format and return the
+    /// next picture in output order, if one is ready.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Option<Picture<'_>>, Box<dyn Error>> {
+        let api = &self.api;
+        let context = self.context.as_ptr();
+        let length = c_int::try_from(data.len())?;
+        // SAFETY: libde265 copies the pushed data before returning.
+        let err =
+            unsafe { (api.push_data)(context, data.as_ptr().cast(), length, 0, ptr::null_mut()) };
+        if unsafe { (api.is_ok)(err) } == 0 {
+            return Err(api.error(err).into());
+        }
+        // The message holds a whole frame, so don't wait for the next one.
+        unsafe { (api.push_end_of_frame)(context) };
+
+        let mut more: c_int = 1;
+        loop {
+            let image = unsafe { (api.peek_next_picture)(context) };
+            if !image.is_null() {
+                return Picture::new(self, image).map(Some);
+            }
+            if more == 0 {
+                return Ok(None);
+            }
+            let err = unsafe { (api.decode)(context, &mut more) };
+            if err == WAITING_FOR_INPUT_DATA {
+                return Ok(None);
+            }
+            if unsafe { (api.is_ok)(err) } == 0 {
+                return Err(api.error(err).into());
+            }
+        }
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe { (self.api.free_decoder)(self.context.as_ptr()) };
+    }
+}
+
+/// A decoded 8-bit 4:2:0 picture, released back to the decoder on drop.
+pub struct Picture<'a> {
+    decoder: &'a Decoder,
+    width: usize,
+    height: usize,
+    planes: [&'a [u8]; 3],
+    strides: [usize; 3],
+}
+
+impl<'a> Picture<'a> {
+    fn new(decoder: &'a Decoder, image: *const Image) -> Result<Self, Box<dyn Error>> {
+        let api = &decoder.api;
+        let release = || unsafe { (api.release_next_picture)(decoder.context.as_ptr()) };
+        // SAFETY: `image` is the picture at the head of the output queue and
+        // stays valid until it is released.
+        let (chroma, bits) = unsafe {
+            (
+                (api.get_chroma_format)(image),
+                (api.get_bits_per_pixel)(image, 0),
+            )
+        };
+        if chroma != CHROMA_420 || bits != 8 {
+            release();
+            return Err(format!(
+                "unsupported HEVC picture format: chroma {chroma}, {bits} bits per pixel"
+            )
+            .into());
+        }
+
+        let mut planes: [&[u8]; 3] = [&[]; 3];
+        let mut strides = [0; 3];
+        for channel in 0..3 {
+            let mut stride: c_int = 0;
+            let (data, height) = unsafe {
+                (
+                    (api.get_image_plane)(image, channel, &mut stride),
+                    (api.get_image_height)(image, channel),
+                )
+            };
+            if data.is_null() {
+                release();
+                return Err("libde265: picture without plane data".into());
+            }
+            let i = channel as usize;
+            strides[i] = stride as usize;
+            planes[i] = unsafe { slice::from_raw_parts(data, strides[i] * height as usize) };
+        }
+        let (width, height) = unsafe {
+            (
+                (api.get_image_width)(image, 0),
+                (api.get_image_height)(image, 0),
+            )
+        };
+        Ok(Picture {
+            decoder,
+            width: width as usize,
+            height: height as usize,
+            planes,
+            strides,
+        })
+    }
+}
+
+impl Drop for Picture<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.decoder.api.release_next_picture)(self.decoder.context.as_ptr()) };
+    }
+}
+
+impl YUVSource for Picture<'_> {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn strides(&self) -> (usize, usize, usize) {
+        (self.strides[0], self.strides[1], self.strides[2])
+    }
+
+    fn y(&self) -> &[u8] {
+        self.planes[0]
+    }
+
+    fn u(&self) -> &[u8] {
+        self.planes[1]
+    }
+
+    fn v(&self) -> &[u8] {
+        self.planes[2]
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-stream decode workers.
+//!
+//! Each camera stream decodes, converts and publishes its frames on its own
+//! thread, fed through a bounded queue (`--decode-queue`). The replay loop
+//! only hands the compressed message over and goes on pacing and publishing
+//! the other topics, so a slow decode overlaps with the wait for the next
+//! message instead of delaying lidar or radar passthrough. When the queue is
+//! full the replay loop waits for the worker: frames are never dropped,
+//! since later frames of a video stream depend on earlier ones.
+//!
+//! A reset (loop restart or seek) discards the frames still queued from the
+//! old position before the stream's decoders are dropped.
+//!
+//! Lockstep replay tracks the frames it waits on: the replay loop waits for
+//! the worker to finish such a frame, so acknowledgements of its outputs
+//! cannot spill over into the next message's wait, and learns whether it
+//! published anything at all, e.g. not before the first keyframe.
+
+use crate::camera::LoopReset;
+use crate::playback::WAIT_POLL;
+use log::{debug, error, info};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{
+            channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError,
+        },
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Work handed to a decode worker.
+pub enum Job {
+    /// A `CompressedVideo` message, decoded without publishing anything
+    /// when `publish` is false.
+    Video { data: Vec<u8>, publish: bool },
+    /// A `CompressedImage` message.
+    Jpeg(Vec<u8>),
+    /// Drop the decoders so the next frame restarts from a keyframe, and
+    /// with [`LoopReset::All`] the image publishers' state too.
+    Reset(LoopReset),
+}
+
+/// A queued job: its reset generation, the job, and where to report whether
+/// it published anything when tracked.
+type Queued = (u64, Job, Option<Sender<bool>>);
+
+/// Completion of a tracked job, from [`DecodeWorker::send_tracked`].
+pub struct Done(Receiver<bool>);
+
+impl Done {
+    /// Wait until the worker has handled the job and return whether it
+    /// published anything. False when a reset skipped the job or `run` was
+    /// cleared while waiting.
+    pub fn wait(self, run: &AtomicBool) -> bool {
+        while run.load(Ordering::Relaxed) {
+            match self.0.recv_timeout(WAIT_POLL) {
+                Ok(published) => return published,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// A camera stream's decode thread and its queue.
+pub struct DecodeWorker {
+    topic: String,
+    queue: Option<SyncSender<Queued>>,
+    /// Bumped by every reset; queued jobs from an older generation are
+    /// skipped.
+    generation: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DecodeWorker {
+    /// Start a worker for `topic` running `handler` on every job, with room
+    /// for `depth` queued jobs. `handler` returns whether the job published
+    /// anything.
+    pub fn spawn(
+        topic: &str,
+        depth: usize,
+        mut handler: impl FnMut(Job) -> bool + Send + 'static,
+    ) -> io::Result<Self> {
+        let (queue, jobs) = sync_channel::<Queued>(depth);
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = generation.clone();
+        let name = topic.to_owned();
+        let thread = thread::Builder::new()
+            .name(format!("decode {topic}"))
+            .spawn(move || {
+                for (job_generation, job, done) in jobs {
+                    let stale = job_generation != current.load(Ordering::Acquire);
+                    let published = match job {
+                        Job::Reset(reset) => handler(Job::Reset(reset)),
+                        _ if stale => {
+                            debug!("Skipping a queued {name} frame after a reset");
+                            false
+                        }
+                        job => handler(job),
+                    };
+                    if let Some(done) = done {
+                        let _ = done.send(published);
+                    }
+                }
+            })?;
+        info!("Decoding {topic} on its own thread (queue depth {depth})");
+        Ok(DecodeWorker {
+            topic: topic.to_owned(),
+            queue: Some(queue),
+            generation,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queue a frame, waiting for room when the queue is full.
+    pub fn send(&self, job: Job) {
+        self.queue(job, None);
+    }
+
+    /// Queue a frame like [`send`](Self::send) and track its completion.
+    pub fn send_tracked(&self, job: Job) -> Done {
+        let (done, completion) = channel();
+        self.queue(job, Some(done));
+        Done(completion)
+    }
+
+    fn queue(&self, job: Job, done: Option<Sender<bool>>) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        let generation = self.generation.load(Ordering::Acquire);
+        let job = match queue.try_send((generation, job, done)) {
+            Ok(()) => return,
+            Err(TrySendError::Full(job)) => job,
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Decode worker for {} has stopped", self.topic);
+                return;
+            }
+        };
+        debug!("Decode queue for {} is full, waiting", self.topic);
+        if queue.send(job).is_err() {
+            error!("Decode worker for {} has stopped", self.topic);
+        }
+    }
+
+    /// Discard the queued frames and reset the stream.
+    pub fn reset(&self, reset: LoopReset) {
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        if let Some(queue) = &self.queue {
+            // The reset itself is queued so it runs after the frame the
+            // worker is busy with.
+            let _ = queue.send((generation, Job::Reset(reset), None));
+        }
+    }
+}
+
+impl Drop for DecodeWorker {
+    /// Finish the queued frames, then stop the thread.
+    fn drop(&mut self) {
+        self.queue = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Decode worker for {} panicked", self.topic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeWorker, Job};
+    use crate::camera::LoopReset;
+    use std::sync::{
+        atomic::AtomicBool,
+        mpsc::{channel, sync_channel},
+        Arc, Mutex,
+    };
+
+    #[test]
+    fn test_reset_skips_queued_frames() {
+        let (started, wait_started) = channel();
+        let (release, blocked) = sync_channel::<()>(0);
+        let blocked = Mutex::new(blocked);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let worker = DecodeWorker::spawn("/camera/h264", 4, move |job| {
+            let entry = match job {
+                Job::Video { data, .. } => data[0],
+                Job::Jpeg(_) => 0,
+                Job::Reset(_) => 255,
+            };
+            if entry == 1 {
+                started.send(()).unwrap();
+                blocked.lock().unwrap().recv().unwrap();
+            }
+            log.lock().unwrap().push(entry);
+            true
+        })
+        .unwrap();
+
+        // Frame 1 is being decoded while 2 and 3 wait in the queue.
+        worker.send(Job::Video {
+            data: vec![1],
+            publish: true,
+        });
+        wait_started.recv().unwrap();
+        let skipped = worker.send_tracked(Job::Video {
+            data: vec![2],
+            publish: true,
+        });
+        worker.send(Job::Video {
+            data: vec![3],
+            publish: true,
+        });
+        worker.reset(LoopReset::Decoders);
+        let decoded = worker.send_tracked(Job::Video {
+            data: vec![4],
+            publish: true,
+        });
+        release.send(()).unwrap();
+
+        // Tracked frames report whether they were handled.
+        let run = AtomicBool::new(true);
+        assert!(decoded.wait(&run));
+        assert!(!skipped.wait(&run));
+        drop(worker);
+        assert_eq!(*seen.lock().unwrap(), [1, 255, 4]);
+    }
+}
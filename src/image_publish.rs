@@ -1,21 +1,48 @@
 // Copyright 2025 Au-Zone Technologies Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Hal-backed RGBA publisher for `rt/camera/image`.
+//! Hal-backed image publisher for `rt/camera/image`.
 //!
-//! Converts decoder-native NV12 frames (h264) or hal-decoded NV12 tensors
-//! (jpeg) to RGBA using `edgefirst_hal::image::ImageProcessor` and publishes
-//! as `sensor_msgs/Image`. Enabled via `--camera-image-topic`.
+//! Converts decoder-native NV12 frames (h264) or hal NV12 tensors (jpeg and
+//! software h264) to `--camera-image-format` (RGBA by default) using
+//! `edgefirst_hal::image::ImageProcessor` and publishes as
+//! `sensor_msgs/Image`. Enabled via `--camera-image-topic`.
+//!
+//! The image is the full visible frame unless `--camera-image-roi` selects a
+//! source region and `--camera-image-size` sets the output size, in which
+//! case `--camera-image-fit` decides how the region is scaled into it:
+//! stretched, letterboxed (aspect kept, black bars) or cropped around the
+//! centre (aspect kept, output filled). This is all expressed through the
+//! `Crop` passed to `ImageProcessor::convert`, so a model input tensor can be
+//! fed directly.
+//!
+//! `--camera-image-flip` and `--camera-image-rotation` (or a per-stream
+//! `--camera-image-orientation`) turn the image the way the live camera
+//! pipeline does: hal flips the scaled region, then rotates it clockwise.
+//! Output size and fit apply to the turned image, so a 90° rotation of a
+//! 1920x1080 frame publishes 1080x1920 unless `--camera-image-size` says
+//! otherwise.
+//!
+//! The same publisher also re-encodes frames for `--camera-jpeg-topic`: the
+//! whole visible frame, turned like the image and divided in size by
+//! `--camera-jpeg-downscale`, is converted to RGB and published as a JPEG
+//! `sensor_msgs/CompressedImage` for viewers that cannot take raw images.
 
+use clap::ValueEnum;
 use edgefirst_hal::image::{Crop, Flip, ImageProcessor, ImageProcessorTrait, Rect, Rotation};
 use edgefirst_hal::tensor::{DType, PixelFormat, TensorDyn, TensorMapTrait, TensorTrait};
-use edgefirst_schemas::{builtin_interfaces::Time, sensor_msgs::Image};
+use edgefirst_schemas::{
+    builtin_interfaces::Time,
+    sensor_msgs::{CompressedImage, Image},
+};
+use jpeg_encoder::{ColorType, Encoder};
 use log::{debug, info};
 use nix::sys::stat::fstat;
 use std::{
     collections::{hash_map::Entry, HashMap},
     error::Error,
     os::fd::BorrowedFd,
+    str::FromStr,
 };
 use tracing::instrument;
 use videostream::frame::Frame;
@@ -25,17 +52,454 @@ use zenoh::{
 };
 
 const ROS_IMAGE_SCHEMA: &str = "sensor_msgs/msg/Image";
-const RGBA_ENCODING: &str = "rgba8";
+const ROS_COMPRESSED_IMAGE_SCHEMA: &str = "sensor_msgs/msg/CompressedImage";
+/// Letterbox bar colour.
+const LETTERBOX_COLOR: [u8; 4] = [0, 0, 0, 255];
+/// Most decoder buffers kept imported at once. Decoder rings are far
+/// smaller, so reaching it means buffers were replaced without the cache
+/// being told; it is then emptied and refilled from the live ring.
+const SRC_CACHE_LIMIT: usize = 32;
+
+/// Output pixel format of the image topic, from `--camera-image-format`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ImageFormat {
+    #[default]
+    #[value(name = "rgba8")]
+    Rgba8,
+    #[value(name = "rgb8")]
+    Rgb8,
+    #[value(name = "bgra8")]
+    Bgra8,
+    /// Converted to `rgb8` by hal, with red and blue swapped while packing.
+    #[value(name = "bgr8")]
+    Bgr8,
+    #[value(name = "mono8")]
+    Mono8,
+    /// Packed YUV 4:2:2 in YUYV byte order.
+    #[value(name = "yuyv")]
+    Yuyv,
+    /// Channels-first RGB: three `width`x`height` planes, with `step` the
+    /// row length of one plane. Its `rgb8_planar` encoding is not one of the
+    /// ROS `sensor_msgs/image_encodings`, so only EdgeFirst consumers that
+    /// know it can read the image.
+    #[value(name = "planar-rgb")]
+    PlanarRgb,
+}
+
+impl ImageFormat {
+    /// Pixel format hal converts into.
+    fn pixel_format(self) -> PixelFormat {
+        match self {
+            ImageFormat::Rgba8 => PixelFormat::Rgba,
+            ImageFormat::Rgb8 | ImageFormat::Bgr8 => PixelFormat::Rgb,
+            ImageFormat::Bgra8 => PixelFormat::Bgra,
+            ImageFormat::Mono8 => PixelFormat::Grey,
+            ImageFormat::Yuyv => PixelFormat::Yuyv,
+            ImageFormat::PlanarRgb => PixelFormat::PlanarRgb,
+        }
+    }
+
+    /// `sensor_msgs/Image` encoding.
+    fn encoding(self) -> &'static str {
+        match self {
+            ImageFormat::Rgba8 => "rgba8",
+            ImageFormat::Rgb8 => "rgb8",
+            ImageFormat::Bgra8 => "bgra8",
+            ImageFormat::Bgr8 => "bgr8",
+            ImageFormat::Mono8 => "mono8",
+            ImageFormat::Yuyv => "yuv422_yuy2",
+            ImageFormat::PlanarRgb => "rgb8_planar",
+        }
+    }
+
+    /// Bytes per row of a `width` pixel wide image, and rows per image of
+    /// `height` lines.
+    fn row_layout(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            ImageFormat::Rgba8 | ImageFormat::Bgra8 => (width * 4, height),
+            ImageFormat::Rgb8 | ImageFormat::Bgr8 => (width * 3, height),
+            ImageFormat::Mono8 => (width, height),
+            ImageFormat::Yuyv => (width * 2, height),
+            ImageFormat::PlanarRgb => (width, height * 3),
+        }
+    }
+}
+
+/// Output size, from `--camera-image-size WxH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for ImageSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|v| *v > 0);
+        match s.split_once(['x', 'X']) {
+            Some((w, h)) => match (parse(w), parse(h)) {
+                (Some(width), Some(height)) => Ok(ImageSize { width, height }),
+                _ => Err(format!("'{s}' is not a WxH image size")),
+            },
+            None => Err(format!("'{s}' is not a WxH image size")),
+        }
+    }
+}
+
+/// Source region of the image in visible-frame pixels, from
+/// `--camera-image-roi left,top,width,height`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRoi(pub Rect);
+
+impl FromStr for ImageRoi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<usize> = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("'{s}' is not of the form left,top,width,height"))?;
+        match values[..] {
+            [left, top, width, height] if width > 0 && height > 0 => {
+                Ok(ImageRoi(Rect::new(left, top, width, height)))
+            }
+            _ => Err(format!("'{s}' is not of the form left,top,width,height")),
+        }
+    }
+}
+
+/// Clockwise rotation of the image, from `--camera-image-rotation`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ImageRotation {
+    #[default]
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Clockwise90,
+    #[value(name = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    CounterClockwise90,
+}
+
+/// Mirroring of the image, from `--camera-image-flip`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ImageFlip {
+    #[default]
+    None,
+    /// Mirror left to right.
+    Horizontal,
+    /// Mirror top to bottom.
+    Vertical,
+}
+
+/// Flip and rotation applied to the image, in that order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: ImageRotation,
+    pub flip: ImageFlip,
+}
+
+impl FromStr for Orientation {
+    type Err = String;
+
+    /// Parses `rotation[,flip]`, e.g. `180` or `90,horizontal`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rotation, flip) = match s.split_once(',') {
+            Some((rotation, flip)) => (rotation, Some(flip)),
+            None => (s, None),
+        };
+        let rotation = ImageRotation::from_str(rotation.trim(), true)
+            .map_err(|_| format!("'{rotation}' is not a rotation of 0, 90, 180 or 270"))?;
+        let flip = match flip {
+            Some(f) => ImageFlip::from_str(f.trim(), true)
+                .map_err(|_| format!("'{f}' is not a flip of none, horizontal or vertical"))?,
+            None => ImageFlip::None,
+        };
+        Ok(Orientation { rotation, flip })
+    }
+}
+
+impl Orientation {
+    /// Whether width and height swap.
+    pub fn quarter_turn(&self) -> bool {
+        matches!(
+            self.rotation,
+            ImageRotation::Clockwise90 | ImageRotation::CounterClockwise90
+        )
+    }
+
+    /// hal rotation and flip, and whether the rows must then be reversed.
+    ///
+    /// hal's CPU backend cannot flip and rotate a quarter turn in one pass,
+    /// so a flipped quarter turn becomes the equivalent unflipped one
+    /// followed by a vertical flip of the output.
+    fn hal(&self) -> (Rotation, Flip, bool) {
+        let flip = match self.flip {
+            ImageFlip::None => Flip::None,
+            ImageFlip::Horizontal => Flip::Horizontal,
+            ImageFlip::Vertical => Flip::Vertical,
+        };
+        match (self.rotation, self.flip) {
+            (ImageRotation::None, _) => (Rotation::None, flip, false),
+            (ImageRotation::Rotate180, _) => (Rotation::Rotate180, flip, false),
+            (ImageRotation::Clockwise90, ImageFlip::None) => (Rotation::Clockwise90, flip, false),
+            (ImageRotation::CounterClockwise90, ImageFlip::None) => {
+                (Rotation::CounterClockwise90, flip, false)
+            }
+            (ImageRotation::Clockwise90, ImageFlip::Horizontal)
+            | (ImageRotation::CounterClockwise90, ImageFlip::Vertical) => {
+                (Rotation::Clockwise90, Flip::None, true)
+            }
+            (ImageRotation::Clockwise90, ImageFlip::Vertical)
+            | (ImageRotation::CounterClockwise90, ImageFlip::Horizontal) => {
+                (Rotation::CounterClockwise90, Flip::None, true)
+            }
+        }
+    }
+
+    /// Map `rect` of the turned `width`x`height` image back to the image
+    /// before rotation and flip. Returns the rectangle and that image's size.
+    pub fn unturn(&self, rect: Rect, width: usize, height: usize) -> (Rect, usize, usize) {
+        let (mut r, w, h) = match self.rotation {
+            ImageRotation::None => (rect, width, height),
+            ImageRotation::Clockwise90 => (
+                Rect::new(
+                    rect.top,
+                    width - rect.left - rect.width,
+                    rect.height,
+                    rect.width,
+                ),
+                height,
+                width,
+            ),
+            ImageRotation::Rotate180 => (
+                Rect::new(
+                    width - rect.left - rect.width,
+                    height - rect.top - rect.height,
+                    rect.width,
+                    rect.height,
+                ),
+                width,
+                height,
+            ),
+            ImageRotation::CounterClockwise90 => (
+                Rect::new(
+                    height - rect.top - rect.height,
+                    rect.left,
+                    rect.height,
+                    rect.width,
+                ),
+                height,
+                width,
+            ),
+        };
+        match self.flip {
+            ImageFlip::None => {}
+            ImageFlip::Horizontal => r.left = w - r.left - r.width,
+            ImageFlip::Vertical => r.top = h - r.top - r.height,
+        }
+        (r, w, h)
+    }
+}
+
+/// How the source region is scaled into `--camera-image-size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ImageFit {
+    /// Scale each axis independently to fill the output.
+    #[default]
+    Stretch,
+    /// Keep the aspect ratio and pad the output with black bars.
+    Letterbox,
+    /// Keep the aspect ratio and crop the source around its centre.
+    CropCenter,
+}
+
+/// Size and framing of the published image.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageGeometry {
+    pub size: Option<ImageSize>,
+    pub fit: ImageFit,
+    pub roi: Option<ImageRoi>,
+    pub orientation: Orientation,
+}
+
+/// JPEG re-encoding settings, from `--camera-jpeg-quality` and
+/// `--camera-jpeg-downscale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegOptions {
+    /// Encoder quality, 1 to 100.
+    pub quality: u8,
+    /// Divisor applied to both sides of the frame.
+    pub downscale: u32,
+}
+
+/// Message a publisher puts on its topic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageOutput {
+    /// `sensor_msgs/Image` in the options' format.
+    #[default]
+    Raw,
+    /// `sensor_msgs/CompressedImage` holding an RGB JPEG.
+    Jpeg(JpegOptions),
+}
+
+/// Everything configuring a camera stream's image output.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageOptions {
+    /// Destination buffers pre-allocated per stream.
+    pub buffers: usize,
+    pub format: ImageFormat,
+    pub geometry: ImageGeometry,
+    pub output: ImageOutput,
+}
+
+impl ImageOptions {
+    /// Options for re-encoding the whole visible frame, turned by
+    /// `orientation`, to JPEG.
+    pub fn jpeg(buffers: usize, orientation: Orientation, jpeg: JpegOptions) -> Self {
+        ImageOptions {
+            buffers,
+            format: ImageFormat::Rgb8,
+            geometry: ImageGeometry {
+                orientation,
+                ..Default::default()
+            },
+            output: ImageOutput::Jpeg(jpeg),
+        }
+    }
+
+    /// Lay out a `visible_width`x`visible_height` frame, applying the JPEG
+    /// downscale when there is one.
+    fn layout(&self, visible_width: u32, visible_height: u32) -> Result<Layout, String> {
+        let layout = self.geometry.layout(visible_width, visible_height)?;
+        match self.output {
+            ImageOutput::Jpeg(JpegOptions { downscale, .. }) if downscale > 1 => ImageGeometry {
+                size: Some(ImageSize {
+                    width: (layout.width / downscale).max(1),
+                    height: (layout.height / downscale).max(1),
+                }),
+                ..self.geometry
+            }
+            .layout(visible_width, visible_height),
+            _ => Ok(layout),
+        }
+    }
+
+    /// Encoding named in logs.
+    fn encoding(&self) -> &'static str {
+        match self.output {
+            ImageOutput::Raw => self.format.encoding(),
+            ImageOutput::Jpeg(_) => "jpeg",
+        }
+    }
+}
+
+/// Where a visible frame lands in the published image: `src` of the frame is
+/// scaled into `dst` of a `width`x`height` image and turned by
+/// `orientation`. `dst` is in published-image pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub src: Rect,
+    pub dst: Rect,
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+}
 
-/// Hal-backed RGBA image publisher.
+impl Layout {
+    /// The whole frame, unscaled.
+    pub fn full(width: u32, height: u32) -> Self {
+        let rect = Rect::new(0, 0, width as usize, height as usize);
+        Layout {
+            src: rect,
+            dst: rect,
+            width,
+            height,
+            orientation: Orientation::default(),
+        }
+    }
+}
+
+impl ImageGeometry {
+    /// Lay out a `visible_width`x`visible_height` frame.
+    pub fn layout(&self, visible_width: u32, visible_height: u32) -> Result<Layout, String> {
+        let (vw, vh) = (visible_width as usize, visible_height as usize);
+        let region = self.roi.map_or(Rect::new(0, 0, vw, vh), |r| r.0);
+        if region.width == 0
+            || region.height == 0
+            || region.left + region.width > vw
+            || region.top + region.height > vh
+        {
+            return Err(format!(
+                "image ROI {},{},{},{} is outside the {vw}x{vh} frame",
+                region.left, region.top, region.width, region.height
+            ));
+        }
+        // Fit the region as it appears after rotation.
+        let quarter = self.orientation.quarter_turn();
+        let (rw, rh) = match quarter {
+            true => (region.height, region.width),
+            false => (region.width, region.height),
+        };
+        let (w, h) = self
+            .size
+            .map_or((rw, rh), |s| (s.width as usize, s.height as usize));
+        let full = Rect::new(0, 0, w, h);
+        // Compare aspect ratios as rw / rh against w / h.
+        let wider = rw * h > rh * w;
+        let (src, dst) = match self.fit {
+            ImageFit::Stretch => (region, full),
+            ImageFit::Letterbox => {
+                let (dw, dh) = if wider {
+                    (w, (rh * w + rw / 2) / rw)
+                } else {
+                    ((rw * h + rh / 2) / rh, h)
+                };
+                let (dw, dh) = (dw.max(1), dh.max(1));
+                (region, Rect::new((w - dw) / 2, (h - dh) / 2, dw, dh))
+            }
+            ImageFit::CropCenter => {
+                let (sw, sh) = if wider {
+                    ((rh * w + h / 2) / h, rh)
+                } else {
+                    (rw, (rw * h + w / 2) / w)
+                };
+                let (sw, sh) = match quarter {
+                    true => (sh.max(1), sw.max(1)),
+                    false => (sw.max(1), sh.max(1)),
+                };
+                let src = Rect::new(
+                    region.left + (region.width - sw) / 2,
+                    region.top + (region.height - sh) / 2,
+                    sw,
+                    sh,
+                );
+                (src, full)
+            }
+        };
+        Ok(Layout {
+            src,
+            dst,
+            width: w as u32,
+            height: h as u32,
+            orientation: self.orientation,
+        })
+    }
+}
+
+/// Hal-backed image publisher.
 ///
-/// Owns a pre-allocated RGBA destination ring (never freed) and an
-/// inode-keyed source-tensor cache (populated lazily as new pool slots
-/// appear, never freed during a decoder session). The processor is created
-/// once and reused.
+/// Owns a pre-allocated destination ring and an inode-keyed source-tensor
+/// cache (populated lazily as new pool slots appear). Both are rebuilt when
+/// the visible frame size changes. The source cache is also emptied when the
+/// decoder owning the buffers is dropped, and never holds more than
+/// [`SRC_CACHE_LIMIT`] entries. The processor is created once and reused.
 pub struct HalImagePublisher {
     topic: String,
-    ring_size: usize,
+    options: ImageOptions,
     cdr_scratch: Vec<u8>,
     state: Option<Ready>,
 }
@@ -47,28 +511,56 @@ struct Ready {
     next_dst: usize,
     visible_width: u32,
     visible_height: u32,
+    layout: Layout,
+    format: ImageFormat,
+    output: ImageOutput,
+    /// Encoded JPEG, reused across frames.
+    jpeg: Vec<u8>,
     /// Scratch buffer used only when the destination tensor is allocated
-    /// with row-stride padding (i.e. effective_row_stride > width*4). For
-    /// GPU-pre-aligned widths (e.g. 1920) hal returns a tight buffer and
-    /// this stays empty.
-    rgba_pack: Vec<u8>,
+    /// with row-stride padding (i.e. effective_row_stride > the packed row
+    /// size) or the channels are swapped while packing. For GPU-pre-aligned
+    /// widths (e.g. 1920) hal returns a tight buffer and this stays empty.
+    pack: Vec<u8>,
 }
 
 impl HalImagePublisher {
-    pub fn new(topic: String, ring_size: usize) -> Self {
+    pub fn new(topic: String, options: ImageOptions) -> Self {
         Self {
             topic,
-            ring_size: ring_size.max(1),
+            options: ImageOptions {
+                buffers: options.buffers.max(1),
+                ..options
+            },
             cdr_scratch: Vec::new(),
             state: None,
         }
     }
 
-    /// Convert a videostream NV12 Frame to RGBA and publish.
+    /// Free the destination ring, source cache and processor. They are
+    /// created again for the next frame.
+    pub fn reset(&mut self) {
+        if self.state.take().is_some() {
+            debug!("Released hal image publisher for {}", self.topic);
+        }
+    }
+
+    /// Forget the imported decoder buffers, e.g. because the decoder owning
+    /// them has been dropped. The destination ring and processor are kept.
+    pub fn release_sources(&mut self) {
+        if let Some(ready) = &mut self.state {
+            debug!(
+                "Released {} hal source buffers for {}",
+                ready.src_cache.len(),
+                self.topic
+            );
+            ready.src_cache.clear();
+        }
+    }
+
+    /// Convert a videostream NV12 Frame and publish.
     ///
-    /// `visible_width`/`visible_height` come from `Decoder::crop()` and pin
-    /// the destination size on first call; subsequent calls must use the
-    /// same values for the publisher's lifetime.
+    /// `visible_width`/`visible_height` come from `Decoder::crop()` and set
+    /// the destination size; a change reallocates the destination ring.
     #[instrument(skip_all)]
     pub fn publish_from_frame(
         &mut self,
@@ -92,12 +584,13 @@ impl HalImagePublisher {
         // `self.topic` / `self.cdr_scratch` are still independently available.
         let Self {
             topic,
-            ring_size,
+            options,
             cdr_scratch,
             state,
         } = self;
-        let ready = ensure_ready(state, *ring_size, topic, visible_width, visible_height)?;
+        let ready = ensure_ready(state, options, topic, visible_width, visible_height)?;
 
+        make_room(&mut ready.src_cache, ino, topic);
         if let Entry::Vacant(slot) = ready.src_cache.entry(ino) {
             let owned = borrowed.try_clone_to_owned()?;
             let format = fourcc_to_pixel_format(frame_fourcc)?;
@@ -114,20 +607,11 @@ impl HalImagePublisher {
             slot.insert(src);
         }
 
-        let src_rect = Rect::new(0, 0, visible_width as usize, visible_height as usize);
-        convert_and_publish(
-            ready,
-            ino,
-            Some(src_rect),
-            stamp,
-            frame_id,
-            topic,
-            session,
-            cdr_scratch,
-        )
+        convert_and_publish(ready, ino, stamp, frame_id, topic, session, cdr_scratch)
     }
 
-    /// Convert an NV12 hal tensor (e.g. jpeg-decoded) to RGBA and publish.
+    /// Convert an NV12 hal tensor (jpeg- or software-decoded, dma-buf or
+    /// shared memory backed) and publish.
     #[instrument(skip_all)]
     pub fn publish_from_tensor(
         &mut self,
@@ -138,22 +622,41 @@ impl HalImagePublisher {
         frame_id: &str,
         session: &Session,
     ) -> Result<(), Box<dyn Error>> {
-        let borrowed = src.dmabuf()?;
-        let ino = fstat(borrowed)?.st_ino;
-        let width = src.width().ok_or("tensor missing width")?;
-        let height = src.height().ok_or("tensor missing height")?;
-        let format = src.format().ok_or("tensor missing format")?;
-
+        let ino = self.import_tensor(src, visible_width, visible_height)?;
         let Self {
             topic,
-            ring_size,
             cdr_scratch,
             state,
+            ..
         } = self;
-        let ready = ensure_ready(state, *ring_size, topic, visible_width, visible_height)?;
+        let ready = state.as_mut().expect("initialised by import_tensor");
+        convert_and_publish(ready, ino, stamp, frame_id, topic, session, cdr_scratch)
+    }
+
+    /// Make sure `src` is in the source cache, initialising the publisher for
+    /// a `visible_width`x`visible_height` frame, and return its cache key.
+    fn import_tensor(
+        &mut self,
+        src: &TensorDyn,
+        visible_width: u32,
+        visible_height: u32,
+    ) -> Result<u64, Box<dyn Error>> {
+        let owned = src.clone_fd()?;
+        let ino = fstat(&owned)?.st_ino;
+        let width = src.width().ok_or("tensor missing width")?;
+        let height = src.height().ok_or("tensor missing height")?;
+        let format = src.format().ok_or("tensor missing format")?;
+
+        let ready = ensure_ready(
+            &mut self.state,
+            &self.options,
+            &self.topic,
+            visible_width,
+            visible_height,
+        )?;
 
+        make_room(&mut ready.src_cache, ino, &self.topic);
         if let Entry::Vacant(slot) = ready.src_cache.entry(ino) {
-            let owned = borrowed.try_clone_to_owned()?;
             let shape = tensor_shape_for(format, width, height)?;
             let mut tensor = TensorDyn::from_fd(owned, &shape, DType::U8, Some("replay-jpeg-src"))?;
             tensor.set_format(format)?;
@@ -168,26 +671,31 @@ impl HalImagePublisher {
             );
             slot.insert(tensor);
         }
+        Ok(ino)
+    }
 
-        let src_rect = Rect::new(0, 0, visible_width as usize, visible_height as usize);
-        convert_and_publish(
-            ready,
-            ino,
-            Some(src_rect),
-            stamp,
-            frame_id,
-            topic,
-            session,
-            cdr_scratch,
-        )
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Layout of a `visible_width`x`visible_height` frame in the published
+    /// image.
+    pub fn layout(&self, visible_width: u32, visible_height: u32) -> Result<Layout, String> {
+        self.options.layout(visible_width, visible_height)
+    }
+}
+
+/// Empty a full source cache before `ino` is added to it.
+fn make_room(src_cache: &mut HashMap<u64, TensorDyn>, ino: u64, topic: &str) {
+    if src_cache.len() >= SRC_CACHE_LIMIT && !src_cache.contains_key(&ino) {
+        debug!("hal source cache for {topic} is full, emptying it");
+        src_cache.clear();
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 fn convert_and_publish(
     ready: &mut Ready,
     src_key: u64,
-    src_rect: Option<Rect>,
     stamp: Time,
     frame_id: &str,
     topic: &str,
@@ -203,59 +711,94 @@ fn convert_and_publish(
         .expect("src cache entry just inserted or already present");
     let dst = &mut ready.dst_ring[dst_idx];
 
-    let crop = Crop::new().with_src_rect(src_rect);
-    ready
-        .processor
-        .convert(src, dst, Rotation::None, Flip::None, crop)?;
-
-    let width = ready.visible_width;
-    let height = ready.visible_height;
-    let step = width * 4;
-
-    // Fast path: if the destination tensor has natural row stride
-    // (`width * 4`), the mmap is already a tightly-packed RGBA frame and we
-    // can pass it directly to the CDR builder. Hal's `create_image` returns
-    // natural-stride buffers for the GPU-pre-aligned widths (640, 1280,
-    // 1920, 3008, 3840). When the allocator pads, fall back to a row-by-row
-    // copy into a scratch buffer kept inside `Ready`.
-    let row_bytes = (width as usize) * 4;
+    let layout = ready.layout;
+    let (rotation, flip, reverse_rows) = layout.orientation.hal();
+    let full = Rect::new(0, 0, layout.width as usize, layout.height as usize);
+    let mut dst_rect = layout.dst;
+    if reverse_rows {
+        dst_rect.top = full.height - dst_rect.top - dst_rect.height;
+    }
+    let crop = match dst_rect == full {
+        true => Crop::new().with_src_rect(Some(layout.src)),
+        false => Crop::new()
+            .with_src_rect(Some(layout.src))
+            .with_dst_rect(Some(dst_rect))
+            .with_dst_color(Some(LETTERBOX_COLOR)),
+    };
+    ready.processor.convert(src, dst, rotation, flip, crop)?;
+
+    let width = layout.width;
+    let height = layout.height;
+    let format = ready.format;
+
+    // Fast path: if the destination tensor has natural row stride, the mmap
+    // is already a tightly-packed frame and we can pass it directly to the
+    // CDR builder. Hal's `create_image` returns natural-stride buffers for
+    // the GPU-pre-aligned widths (640, 1280, 1920, 3008, 3840). When the
+    // allocator pads, `bgr8` needs its channels swapped or the rows must be
+    // reversed, fall back to a row-by-row copy into a scratch buffer kept
+    // inside `Ready`.
+    let (row_bytes, rows) = format.row_layout(width as usize, height as usize);
+    let step = row_bytes as u32;
     let stride = dst.effective_row_stride().unwrap_or(row_bytes);
     let tensor_u8 = dst
         .as_u8()
         .ok_or("hal destination tensor is not u8-backed")?;
     let map = tensor_u8.map()?;
     let src = map.as_slice();
-    let height_usize = height as usize;
-
-    let enc = Encoding::APPLICATION_CDR.with_schema(ROS_IMAGE_SCHEMA);
-    if stride == row_bytes {
-        let needed = row_bytes * height_usize;
-        Image::builder()
-            .stamp(stamp)
-            .frame_id(frame_id)
-            .height(height)
-            .width(width)
-            .encoding(RGBA_ENCODING)
-            .step(step)
-            .data(&src[..needed])
-            .encode_into_vec(cdr_scratch)?;
+
+    let data = if stride == row_bytes && format != ImageFormat::Bgr8 && !reverse_rows {
+        &src[..row_bytes * rows]
     } else {
-        ready.rgba_pack.resize(row_bytes * height_usize, 0);
-        for row in 0..height_usize {
-            let s = row * stride;
+        ready.pack.resize(row_bytes * rows, 0);
+        // Planar formats reverse the rows of each plane.
+        let plane_rows = height as usize;
+        for row in 0..rows {
+            let from = match reverse_rows {
+                true => row - row % plane_rows + plane_rows - 1 - row % plane_rows,
+                false => row,
+            };
+            let s = from * stride;
             let e = s + row_bytes;
-            ready.rgba_pack[row * row_bytes..(row + 1) * row_bytes].copy_from_slice(&src[s..e]);
+            ready.pack[row * row_bytes..(row + 1) * row_bytes].copy_from_slice(&src[s..e]);
         }
-        Image::builder()
-            .stamp(stamp)
-            .frame_id(frame_id)
-            .height(height)
-            .width(width)
-            .encoding(RGBA_ENCODING)
-            .step(step)
-            .data(&ready.rgba_pack)
-            .encode_into_vec(cdr_scratch)?;
-    }
+        if format == ImageFormat::Bgr8 {
+            for pixel in ready.pack.chunks_exact_mut(3) {
+                pixel.swap(0, 2);
+            }
+        }
+        &ready.pack
+    };
+    let enc = match ready.output {
+        ImageOutput::Raw => {
+            Image::builder()
+                .stamp(stamp)
+                .frame_id(frame_id)
+                .height(height)
+                .width(width)
+                .encoding(format.encoding())
+                .step(step)
+                .data(data)
+                .encode_into_vec(cdr_scratch)?;
+            Encoding::APPLICATION_CDR.with_schema(ROS_IMAGE_SCHEMA)
+        }
+        ImageOutput::Jpeg(jpeg) => {
+            ready.jpeg.clear();
+            Encoder::new(&mut ready.jpeg, jpeg.quality).encode(
+                data,
+                u16::try_from(width)?,
+                u16::try_from(height)?,
+                ColorType::Rgb,
+            )?;
+            CompressedImage::builder()
+                .stamp(stamp)
+                .frame_id(frame_id)
+                .format("jpeg")
+                .data(&ready.jpeg)
+                .encode_into_vec(cdr_scratch)?;
+            Encoding::APPLICATION_CDR.with_schema(ROS_COMPRESSED_IMAGE_SCHEMA)
+        }
+    };
     drop(map);
 
     session
@@ -268,23 +811,48 @@ fn convert_and_publish(
 
 fn ensure_ready<'a>(
     state: &'a mut Option<Ready>,
-    ring_size: usize,
+    options: &ImageOptions,
     topic: &str,
     visible_width: u32,
     visible_height: u32,
 ) -> Result<&'a mut Ready, Box<dyn Error>> {
-    if state.is_none() {
+    let resized = state
+        .as_ref()
+        .is_some_and(|r| (r.visible_width, r.visible_height) != (visible_width, visible_height));
+    if state.is_none() || resized {
+        let layout = options.layout(visible_width, visible_height)?;
+        // Published images are copies, so nothing outside this publisher
+        // still refers to the old destination ring. The cached source
+        // tensors only hold duplicated fds of the old decoder buffers.
+        let processor = match state.take() {
+            Some(old) => {
+                info!(
+                    "Camera frame size changed {}x{} -> {}x{}; reallocating hal image \
+                     publisher for {}",
+                    old.visible_width, old.visible_height, visible_width, visible_height, topic
+                );
+                old.processor
+            }
+            None => ImageProcessor::new()?,
+        };
         info!(
-            "Initialising hal image publisher: {}x{} ring={} topic={}",
-            visible_width, visible_height, ring_size, topic
+            "Initialising hal image publisher: {}x{} -> {}x{} {} ({:?}, {:?}) ring={} topic={}",
+            visible_width,
+            visible_height,
+            layout.width,
+            layout.height,
+            options.encoding(),
+            options.geometry.fit,
+            options.geometry.orientation,
+            options.buffers,
+            topic
         );
-        let processor = ImageProcessor::new()?;
-        let mut dst_ring = Vec::with_capacity(ring_size);
-        for _ in 0..ring_size {
+        let mut dst_ring = Vec::with_capacity(options.buffers);
+        for _ in 0..options.buffers {
             let t = processor.create_image(
-                visible_width as usize,
-                visible_height as usize,
-                PixelFormat::Rgba,
+                layout.width as usize,
+                layout.height as usize,
+                options.format.pixel_format(),
                 DType::U8,
                 None,
             )?;
@@ -297,19 +865,14 @@ fn ensure_ready<'a>(
             next_dst: 0,
             visible_width,
             visible_height,
-            rgba_pack: Vec::new(),
+            layout,
+            format: options.format,
+            output: options.output,
+            jpeg: Vec::new(),
+            pack: Vec::new(),
         });
     }
-    let ready = state.as_mut().expect("just initialised");
-    if ready.visible_width != visible_width || ready.visible_height != visible_height {
-        return Err(format!(
-            "hal publisher dims changed {}x{} -> {}x{}; skipping publish \
-             (dst ring sized for the original dimensions)",
-            ready.visible_width, ready.visible_height, visible_width, visible_height
-        )
-        .into());
-    }
-    Ok(ready)
+    Ok(state.as_mut().expect("just initialised"))
 }
 
 fn fourcc_to_pixel_format(fourcc: u32) -> Result<PixelFormat, Box<dyn Error>> {
@@ -341,3 +904,209 @@ fn tensor_shape_for(
         other => Err(format!("unsupported pixel format {other:?}").into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        HalImagePublisher, ImageFit, ImageFlip, ImageFormat, ImageGeometry, ImageOptions, ImageRoi,
+        ImageRotation, ImageSize, JpegOptions, Layout, Orientation, SRC_CACHE_LIMIT,
+    };
+    use edgefirst_hal::{
+        image::{Flip, Rect, Rotation},
+        tensor::{DType, PixelFormat, TensorDyn, TensorMemory},
+    };
+
+    /// A decoder's frame ring: `depth` fresh NV12 buffers.
+    fn decoder_ring(depth: usize) -> Vec<TensorDyn> {
+        (0..depth)
+            .map(|_| {
+                TensorDyn::image(
+                    64,
+                    48,
+                    PixelFormat::Nv12,
+                    DType::U8,
+                    Some(TensorMemory::Shm),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_src_cache_bounded() {
+        let options = ImageOptions {
+            buffers: 2,
+            format: ImageFormat::Rgba8,
+            geometry: ImageGeometry::default(),
+            output: Default::default(),
+        };
+        let cached = |p: &HalImagePublisher| p.state.as_ref().map_or(0, |r| r.src_cache.len());
+
+        // Every seek or loop restart replaces the decoder and its ring; the
+        // stream reset releases the imports of the old ring.
+        let mut publisher = HalImagePublisher::new("rt/camera/image".to_owned(), options);
+        for _ in 0..20 {
+            let ring = decoder_ring(4);
+            for _ in 0..3 {
+                for frame in &ring {
+                    publisher.import_tensor(frame, 64, 48).unwrap();
+                }
+            }
+            assert_eq!(cached(&publisher), 4);
+            publisher.release_sources();
+        }
+        assert_eq!(cached(&publisher), 0);
+
+        // Buffers replaced without a reset are capped. The old rings are
+        // kept alive so their inodes aren't reused.
+        let rings: Vec<_> = (0..20).map(|_| decoder_ring(4)).collect();
+        let mut most = 0;
+        for frame in rings.iter().flatten() {
+            publisher.import_tensor(frame, 64, 48).unwrap();
+            most = most.max(cached(&publisher));
+        }
+        assert_eq!(most, SRC_CACHE_LIMIT);
+    }
+
+    #[test]
+    fn test_layout() {
+        let geometry = |size: Option<&str>, fit, roi: Option<&str>| ImageGeometry {
+            size: size.map(|s| s.parse().unwrap()),
+            fit,
+            roi: roi.map(|r| r.parse().unwrap()),
+            orientation: Orientation::default(),
+        };
+
+        let full = geometry(None, ImageFit::Stretch, None);
+        assert_eq!(full.layout(1920, 1080).unwrap(), Layout::full(1920, 1080));
+
+        let stretch = geometry(Some("640x640"), ImageFit::Stretch, None);
+        let l = stretch.layout(1920, 1080).unwrap();
+        assert_eq!(l.src, Rect::new(0, 0, 1920, 1080));
+        assert_eq!(l.dst, Rect::new(0, 0, 640, 640));
+
+        let letterbox = geometry(Some("640x640"), ImageFit::Letterbox, None);
+        let l = letterbox.layout(1920, 1080).unwrap();
+        assert_eq!(l.dst, Rect::new(0, 140, 640, 360));
+
+        let crop = geometry(Some("640x640"), ImageFit::CropCenter, None);
+        let l = crop.layout(1920, 1080).unwrap();
+        assert_eq!(l.src, Rect::new(420, 0, 1080, 1080));
+        assert_eq!(l.dst, Rect::new(0, 0, 640, 640));
+
+        let roi = geometry(None, ImageFit::Letterbox, Some("100,50,640,480"));
+        let l = roi.layout(1920, 1080).unwrap();
+        assert_eq!((l.width, l.height), (640, 480));
+        assert_eq!(l.src, Rect::new(100, 50, 640, 480));
+        assert!(roi.layout(640, 480).is_err());
+
+        // A quarter turn swaps the output size, and the fit applies to the
+        // turned region: a centre crop to 640x480 takes 810 source rows.
+        let turned = |mut g: ImageGeometry, o: &str| {
+            g.orientation = o.parse().unwrap();
+            g
+        };
+        let l = turned(full, "90").layout(1920, 1080).unwrap();
+        assert_eq!((l.width, l.height), (1080, 1920));
+        let crop = geometry(Some("640x480"), ImageFit::CropCenter, None);
+        let l = turned(crop, "270").layout(1920, 1080).unwrap();
+        assert_eq!(l.src, Rect::new(555, 0, 810, 1080));
+        let l = turned(letterbox, "90").layout(1920, 1080).unwrap();
+        assert_eq!(l.dst, Rect::new(140, 0, 360, 640));
+    }
+
+    #[test]
+    fn test_jpeg_layout() {
+        let jpeg = |o: &str, downscale| {
+            let jpeg = JpegOptions {
+                quality: 80,
+                downscale,
+            };
+            ImageOptions::jpeg(4, o.parse().unwrap(), jpeg)
+        };
+        assert_eq!(
+            jpeg("0", 1).layout(1920, 1080).unwrap(),
+            Layout::full(1920, 1080)
+        );
+        let l = jpeg("0", 3).layout(1920, 1080).unwrap();
+        assert_eq!((l.width, l.height), (640, 360));
+        assert_eq!(l.src, Rect::new(0, 0, 1920, 1080));
+        let l = jpeg("90", 2).layout(1920, 1080).unwrap();
+        assert_eq!((l.width, l.height), (540, 960));
+    }
+
+    #[test]
+    fn test_unturn() {
+        let rect = Rect::new(10, 20, 100, 50);
+        let unturn = |o: &str| o.parse::<Orientation>().unwrap().unturn(rect, 640, 480);
+        assert_eq!(unturn("0"), (rect, 640, 480));
+        assert_eq!(
+            unturn("0,horizontal"),
+            (Rect::new(530, 20, 100, 50), 640, 480)
+        );
+        assert_eq!(unturn("180"), (Rect::new(530, 410, 100, 50), 640, 480));
+        assert_eq!(unturn("90"), (Rect::new(20, 530, 50, 100), 480, 640));
+        assert_eq!(unturn("270"), (Rect::new(410, 10, 50, 100), 480, 640));
+        assert_eq!(
+            unturn("90,vertical"),
+            (Rect::new(20, 10, 50, 100), 480, 640)
+        );
+
+        // hal's unflipped rotation followed by the row reversal turns the
+        // image the same way as the requested flip and rotation.
+        for o in [
+            "90,horizontal",
+            "90,vertical",
+            "270,horizontal",
+            "270,vertical",
+        ] {
+            let o: Orientation = o.parse().unwrap();
+            let (rotation, flip, reverse_rows) = o.hal();
+            assert!(reverse_rows && flip == Flip::None);
+            let rotation = match rotation {
+                Rotation::Clockwise90 => "90",
+                _ => "270",
+            };
+            let reversed = Rect::new(rect.left, 480 - rect.top - rect.height, 100, 50);
+            let hal = rotation.parse::<Orientation>().unwrap();
+            assert_eq!(hal.unturn(reversed, 640, 480), o.unturn(rect, 640, 480));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "640x480".parse::<ImageSize>(),
+            Ok(ImageSize {
+                width: 640,
+                height: 480
+            })
+        );
+        assert!("640".parse::<ImageSize>().is_err());
+        assert!("0x480".parse::<ImageSize>().is_err());
+        assert_eq!(
+            "0,0,320,240".parse::<ImageRoi>(),
+            Ok(ImageRoi(Rect::new(0, 0, 320, 240)))
+        );
+        assert!("0,0,320".parse::<ImageRoi>().is_err());
+        assert_eq!(
+            "90,Horizontal".parse::<Orientation>(),
+            Ok(Orientation {
+                rotation: ImageRotation::Clockwise90,
+                flip: ImageFlip::Horizontal,
+            })
+        );
+        assert_eq!("180".parse::<Orientation>().unwrap().flip, ImageFlip::None);
+        assert!("45".parse::<Orientation>().is_err());
+        assert!("90,diagonal".parse::<Orientation>().is_err());
+    }
+
+    #[test]
+    fn test_row_layout() {
+        assert_eq!(ImageFormat::Rgba8.row_layout(640, 480), (2560, 480));
+        assert_eq!(ImageFormat::Bgr8.row_layout(640, 480), (1920, 480));
+        assert_eq!(ImageFormat::Mono8.row_layout(640, 480), (640, 480));
+        assert_eq!(ImageFormat::Yuyv.row_layout(640, 480), (1280, 480));
+        assert_eq!(ImageFormat::PlanarRgb.row_layout(640, 480), (640, 1440));
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording details for `--list`.
+//!
+//! Recordings with summary statistics are described from the summary alone:
+//! statistics for the message counts, time span and average rates, channels
+//! for the schemas, and chunk indexes for the compression. Each channel's
+//! first and last log time, maximum rate and size need the messages
+//! themselves, so they are only reported when the messages are scanned:
+//! with `--scan`, or when there are no statistics. Indexed recordings are
+//! then streamed chunk by chunk through the chunk indexes, and recordings
+//! without chunk indexes, e.g. truncated files, are scanned linearly up to
+//! the first unreadable record.
+
+use crate::{playlist::Recording, remap::TopicPrefix};
+use log::warn;
+use mcap::{records::Record, McapResult, Summary};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Output format of `--list`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListFormat {
+    Text,
+    Json,
+}
+
+pub struct ChannelInfo {
+    pub topic: String,
+    pub schema: String,
+    pub schema_encoding: String,
+    pub message_encoding: String,
+    pub count: u64,
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+    /// Mean messages per second between the first and last message, or
+    /// over the whole recording when the messages weren't scanned.
+    pub avg_rate: f64,
+    /// Most messages within any one-second window, and never less than
+    /// `avg_rate`. Only known when the messages were scanned.
+    pub max_rate: Option<f64>,
+    pub bytes: Option<u64>,
+}
+
+pub struct FileInfo {
+    pub path: String,
+    pub profile: String,
+    pub library: String,
+    pub count: u64,
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+    pub compression: BTreeSet<String>,
+    /// Whether the recording has chunk indexes.
+    pub indexed: bool,
+    /// Whether the messages were read for the per-channel details rather
+    /// than taken from the summary statistics.
+    pub scanned: bool,
+    pub channels: Vec<ChannelInfo>,
+}
+
+/// Per-channel accumulator.
+#[derive(Default)]
+struct ChannelStats {
+    times: Vec<u64>,
+    bytes: u64,
+}
+
+struct ChannelMeta {
+    topic: String,
+    schema: String,
+    schema_encoding: String,
+    message_encoding: String,
+}
+
+impl ChannelMeta {
+    fn new(channel: &mcap::Channel) -> Self {
+        let (schema, schema_encoding) = match &channel.schema {
+            Some(s) => (s.name.clone(), s.encoding.clone()),
+            None => (String::new(), String::new()),
+        };
+        ChannelMeta {
+            topic: channel.topic.clone(),
+            schema,
+            schema_encoding,
+            message_encoding: channel.message_encoding.clone(),
+        }
+    }
+}
+
+impl FileInfo {
+    /// Describe `recording`, reading its messages when `scan` is set or the
+    /// summary has no statistics.
+    pub fn collect(recording: &Recording, scan: bool) -> McapResult<Self> {
+        let mapped: &[u8] = &recording.mapped;
+        let (profile, library) = header(mapped);
+        let mut info = FileInfo {
+            path: recording.path.display().to_string(),
+            profile,
+            library,
+            count: 0,
+            start: None,
+            end: None,
+            compression: BTreeSet::new(),
+            indexed: false,
+            scanned: true,
+            channels: Vec::new(),
+        };
+
+        let mut metas = BTreeMap::new();
+        let mut stats: BTreeMap<u16, ChannelStats> = BTreeMap::new();
+        match &recording.summary {
+            Some(summary) if !scan && summary.stats.is_some() => {
+                info.indexed = !summary.chunk_indexes.is_empty();
+                info.scanned = false;
+                for chunk in &summary.chunk_indexes {
+                    info.compression
+                        .insert(compression_name(&chunk.compression));
+                }
+            }
+            Some(summary) if !summary.chunk_indexes.is_empty() => {
+                info.indexed = true;
+                for (id, channel) in &summary.channels {
+                    metas.insert(*id, ChannelMeta::new(channel));
+                    stats.entry(*id).or_default();
+                }
+                scan_chunks(mapped, summary, &mut stats)?;
+                for chunk in &summary.chunk_indexes {
+                    info.compression
+                        .insert(compression_name(&chunk.compression));
+                }
+            }
+            _ => {
+                for record in mcap::read::LinearReader::new_with_options(
+                    mapped,
+                    mcap::read::Options::IgnoreEndMagic.into(),
+                )? {
+                    let record = match record {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("Stopped scanning {:?} at {:?}", recording.path, e);
+                            break;
+                        }
+                    };
+                    if let Record::Chunk { header, .. } = record {
+                        info.compression
+                            .insert(compression_name(&header.compression));
+                    }
+                }
+                for message in mcap::MessageStream::new_with_options(
+                    mapped,
+                    mcap::read::Options::IgnoreEndMagic.into(),
+                )? {
+                    let Ok(message) = message else {
+                        break;
+                    };
+                    let id = message.channel.id;
+                    metas
+                        .entry(id)
+                        .or_insert_with(|| ChannelMeta::new(&message.channel));
+                    let s = stats.entry(id).or_default();
+                    s.times.push(message.log_time);
+                    s.bytes += message.data.len() as u64;
+                }
+            }
+        }
+
+        for (id, mut s) in stats {
+            let Some(meta) = metas.remove(&id) else {
+                continue;
+            };
+            s.times.sort_unstable();
+            let count = s.times.len() as u64;
+            let first = s.times.first().copied();
+            let last = s.times.last().copied();
+            let avg_rate = avg_rate(&s.times);
+            info.channels.push(ChannelInfo {
+                topic: meta.topic,
+                schema: meta.schema,
+                schema_encoding: meta.schema_encoding,
+                message_encoding: meta.message_encoding,
+                count,
+                first,
+                last,
+                avg_rate,
+                max_rate: Some(max_rate(&s.times)),
+                bytes: Some(s.bytes),
+            });
+        }
+
+        match recording.summary.as_ref().and_then(|s| s.stats.as_ref()) {
+            Some(stats) => {
+                info.count = stats.message_count;
+                if stats.message_count > 0 {
+                    info.start = Some(stats.message_start_time);
+                    info.end = Some(stats.message_end_time);
+                }
+            }
+            None => {
+                info.count = info.channels.iter().map(|c| c.count).sum();
+                info.start = info.channels.iter().filter_map(|c| c.first).min();
+                info.end = info.channels.iter().filter_map(|c| c.last).max();
+            }
+        }
+        if let (false, Some(summary)) = (info.scanned, &recording.summary) {
+            info.channels = summary_channels(summary, info.duration());
+        }
+        info.channels
+            .sort_by(|a, b| (&a.topic, &a.schema).cmp(&(&b.topic, &b.schema)));
+        Ok(info)
+    }
+
+    fn duration(&self) -> f64 {
+        match (self.start, self.end) {
+            (Some(s), Some(e)) => e.saturating_sub(s) as f64 / 1e9,
+            _ => 0.0,
+        }
+    }
+
+    pub fn to_json(&self, prefix: &TopicPrefix) -> Value {
+        let channels: Vec<Value> = self
+            .channels
+            .iter()
+            .map(|c| {
+                json!({
+                    "topic": c.topic,
+                    "key": prefix.key(&c.topic),
+                    "schema": c.schema,
+                    "schema_encoding": c.schema_encoding,
+                    "message_encoding": c.message_encoding,
+                    "count": c.count,
+                    "first_log_time": c.first,
+                    "last_log_time": c.last,
+                    "avg_rate": c.avg_rate,
+                    "max_rate": c.max_rate,
+                    "bytes": c.bytes,
+                })
+            })
+            .collect();
+        json!({
+            "file": self.path,
+            "profile": self.profile,
+            "library": self.library,
+            "indexed": self.indexed,
+            "scanned": self.scanned,
+            "message_count": self.count,
+            "start_log_time": self.start,
+            "end_log_time": self.end,
+            "duration": self.duration(),
+            "compression": self.compression,
+            "channels": channels,
+        })
+    }
+
+    pub fn print(&self, prefix: &TopicPrefix) {
+        println!("File:        {}", self.path);
+        println!("Library:     {}", self.library);
+        println!("Profile:     {}", self.profile);
+        println!(
+            "Messages:    {} ({})",
+            self.count,
+            if self.scanned { "scanned" } else { "summary" }
+        );
+        match (self.start, self.end) {
+            (Some(s), Some(e)) => {
+                println!("Duration:    {:.3}s ({s} .. {e})", self.duration())
+            }
+            _ => println!("Duration:    -"),
+        }
+        let compression: Vec<&str> = self.compression.iter().map(String::as_str).collect();
+        println!("Compression: {}", compression.join(", "));
+        println!();
+
+        let rows: Vec<[String; 9]> = self
+            .channels
+            .iter()
+            .map(|c| {
+                let offset = |t: Option<u64>| match (t, self.start) {
+                    (Some(t), Some(s)) => format!("{:.3}s", t.saturating_sub(s) as f64 / 1e9),
+                    _ => "-".to_owned(),
+                };
+                [
+                    prefix.key(&c.topic),
+                    c.schema.clone(),
+                    format!("{}/{}", c.message_encoding, c.schema_encoding),
+                    c.count.to_string(),
+                    offset(c.first),
+                    offset(c.last),
+                    format!("{:.2}", c.avg_rate),
+                    c.max_rate.map_or("-".to_owned(), |r| format!("{r:.0}")),
+                    c.bytes.map_or("-".to_owned(), |b| b.to_string()),
+                ]
+            })
+            .collect();
+        let titles = [
+            "TOPIC", "SCHEMA", "ENCODING", "COUNT", "FIRST", "LAST", "AVG HZ", "MAX HZ", "BYTES",
+        ];
+        let mut widths = titles.map(str::len);
+        for row in &rows {
+            for (w, cell) in widths.iter_mut().zip(row) {
+                *w = (*w).max(cell.len());
+            }
+        }
+        let line = |cells: [&str; 9]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(widths)
+                .enumerate()
+                .map(|(i, (cell, w))| match i {
+                    0..=2 => format!("{cell:<w$}"),
+                    _ => format!("{cell:>w$}"),
+                })
+                .collect();
+            println!("{}", cells.join("  ").trim_end());
+        };
+        line(titles);
+        for row in &rows {
+            line(row.each_ref().map(String::as_str));
+        }
+    }
+}
+
+/// Profile and library from the file header record.
+fn header(mapped: &[u8]) -> (String, String) {
+    let first = mcap::read::LinearReader::new_with_options(
+        mapped,
+        mcap::read::Options::IgnoreEndMagic.into(),
+    )
+    .ok()
+    .and_then(|mut r| r.next());
+    match first {
+        Some(Ok(Record::Header(h))) => (h.profile, h.library),
+        _ => (String::new(), String::new()),
+    }
+}
+
+fn compression_name(compression: &str) -> String {
+    if compression.is_empty() {
+        "none".to_owned()
+    } else {
+        compression.to_owned()
+    }
+}
+
+/// Channels described from the summary statistics alone, averaging each
+/// channel's rate over the recording's `duration` in seconds.
+fn summary_channels(summary: &Summary, duration: f64) -> Vec<ChannelInfo> {
+    let counts = summary.stats.as_ref().map(|s| &s.channel_message_counts);
+    summary
+        .channels
+        .iter()
+        .map(|(id, channel)| {
+            let meta = ChannelMeta::new(channel);
+            let count = counts.and_then(|c| c.get(id)).copied().unwrap_or(0);
+            ChannelInfo {
+                topic: meta.topic,
+                schema: meta.schema,
+                schema_encoding: meta.schema_encoding,
+                message_encoding: meta.message_encoding,
+                count,
+                first: None,
+                last: None,
+                avg_rate: if duration > 0.0 {
+                    count.saturating_sub(1) as f64 / duration
+                } else {
+                    0.0
+                },
+                max_rate: None,
+                bytes: None,
+            }
+        })
+        .collect()
+}
+
+/// Collect message times and sizes from every indexed chunk.
+fn scan_chunks(
+    mapped: &[u8],
+    summary: &Summary,
+    stats: &mut BTreeMap<u16, ChannelStats>,
+) -> McapResult<()> {
+    for chunk in &summary.chunk_indexes {
+        for message in summary.stream_chunk(mapped, chunk)? {
+            let message = message?;
+            let s = stats.entry(message.channel.id).or_default();
+            s.times.push(message.log_time);
+            s.bytes += message.data.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+/// Mean messages per second between the first and last of the sorted log
+/// times.
+fn avg_rate(times: &[u64]) -> f64 {
+    match (times.first(), times.last()) {
+        (Some(f), Some(l)) if l > f => (times.len() - 1) as f64 / ((l - f) as f64 / 1e9),
+        _ => 0.0,
+    }
+}
+
+/// Largest number of messages within any one-second window of the sorted
+/// log times, floored at the average rate: a channel spanning less than a
+/// second never fills a window.
+fn max_rate(times: &[u64]) -> f64 {
+    let mut max = 0;
+    let mut start = 0;
+    for (end, &t) in times.iter().enumerate() {
+        while t - times[start] >= 1_000_000_000 {
+            start += 1;
+        }
+        max = max.max(end - start + 1);
+    }
+    (max as f64).max(avg_rate(times))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{avg_rate, max_rate, summary_channels};
+    use mcap::{records::Statistics, Channel, Summary};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    };
+
+    #[test]
+    fn test_max_rate() {
+        assert_eq!(max_rate(&[]), 0.0);
+        let times: Vec<u64> = (0..30).map(|i| i * 100_000_000).collect();
+        assert_eq!(max_rate(&times), 10.0);
+        assert_eq!(avg_rate(&times), 10.0);
+        let burst = [0, 1, 2, 3, 2_000_000_000];
+        assert_eq!(max_rate(&burst), 4.0);
+
+        // Ten messages over 0.3 s: 30 Hz, though no window holds more than
+        // ten of them.
+        let short: Vec<u64> = (0..10).map(|i| i * 100_000_000 / 3).collect();
+        let avg = avg_rate(&short);
+        assert!((avg - 30.0).abs() < 1e-6, "avg {avg}");
+        assert_eq!(max_rate(&short), avg);
+    }
+
+    #[test]
+    fn test_summary_channels() {
+        let channel = |id, topic: &str| {
+            let channel = Channel {
+                id,
+                topic: topic.to_owned(),
+                schema: None,
+                message_encoding: "cdr".to_owned(),
+                metadata: BTreeMap::new(),
+            };
+            (id, Arc::new(channel))
+        };
+        let summary = Summary {
+            stats: Some(Statistics {
+                message_count: 31,
+                channel_message_counts: BTreeMap::from([(1, 31)]),
+                ..Default::default()
+            }),
+            channels: HashMap::from([channel(1, "/lidar/points"), channel(2, "/gps")]),
+            ..Default::default()
+        };
+        let mut channels = summary_channels(&summary, 3.0);
+        channels.sort_by(|a, b| a.topic.cmp(&b.topic));
+        assert_eq!(channels[0].topic, "/gps");
+        assert_eq!(channels[0].count, 0);
+        assert_eq!(channels[0].avg_rate, 0.0);
+        assert_eq!(channels[1].count, 31);
+        assert_eq!(channels[1].avg_rate, 10.0);
+        // Only a scan knows these.
+        assert!(channels[1].first.is_none() && channels[1].max_rate.is_none());
+        assert!(channels[1].bytes.is_none());
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Late-message policies and counters.
+//!
+//! A message is late when the replay loop reaches it more than
+//! [`LATE_AFTER`] behind its time on the playback clock, e.g. because a
+//! decode queue was full. Each topic's policy (`--late-policy`,
+//! `--topic-late-policy`) decides what happens to its late messages: publish
+//! them anyway, drop them once they are older than a limit, or drop all but
+//! the newest of the messages already due on that topic. Late and dropped
+//! messages are counted per topic for the replay status and the log.
+//!
+//! A dropped video frame is still decoded, since the frames after it depend
+//! on it; only its passthrough message and decoded outputs are skipped.
+
+use std::{collections::BTreeMap, str::FromStr, sync::Mutex, time::Duration};
+
+/// How far behind schedule a message may be published before it counts as
+/// late.
+pub const LATE_AFTER: Duration = Duration::from_millis(5);
+
+/// What to do with a topic's late messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatePolicy {
+    /// Publish late messages as soon as the loop gets to them.
+    #[default]
+    Publish,
+    /// Drop messages that are further behind schedule than this.
+    Drop(Duration),
+    /// Drop late messages when a newer message on the same topic is already
+    /// due.
+    Latest,
+}
+
+impl LatePolicy {
+    /// Whether a message `lag` behind schedule is dropped; `superseded` is
+    /// set when a newer message on its topic is already due.
+    pub fn drops(&self, lag: Duration, superseded: bool) -> bool {
+        match self {
+            LatePolicy::Publish => false,
+            LatePolicy::Drop(limit) => lag > *limit,
+            LatePolicy::Latest => superseded,
+        }
+    }
+}
+
+impl FromStr for LatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "publish" => Ok(LatePolicy::Publish),
+            "latest" => Ok(LatePolicy::Latest),
+            _ => match s.strip_prefix("drop:") {
+                Some(age) => crate::args::parse_duration(age).map(LatePolicy::Drop),
+                None => Err(format!(
+                    "'{s}' is not a late policy (publish, latest or drop:<age>)"
+                )),
+            },
+        }
+    }
+}
+
+/// Late policy for one topic, from `--topic-late-policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicLatePolicy {
+    pub topic: String,
+    pub policy: LatePolicy,
+}
+
+impl FromStr for TopicLatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (topic, policy) = s
+            .rsplit_once('=')
+            .filter(|(topic, _)| !topic.is_empty())
+            .ok_or_else(|| format!("'{s}' is not of the form topic=policy"))?;
+        Ok(TopicLatePolicy {
+            topic: topic.to_owned(),
+            policy: policy.parse()?,
+        })
+    }
+}
+
+/// Late and dropped messages on one topic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LateCount {
+    pub late: u64,
+    pub dropped: u64,
+}
+
+/// Per-topic late message counters, shared with the status publisher.
+#[derive(Debug, Default)]
+pub struct LateCounters(Mutex<BTreeMap<String, LateCount>>);
+
+impl LateCounters {
+    /// Count a late message on `topic`, and whether it was dropped.
+    pub fn record(&self, topic: &str, dropped: bool) {
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(topic.to_owned()).or_default();
+        count.late += 1;
+        count.dropped += u64::from(dropped);
+    }
+
+    /// Counts for every topic that has had a late message.
+    pub fn snapshot(&self) -> BTreeMap<String, LateCount> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LateCount, LateCounters, LatePolicy, TopicLatePolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("publish".parse(), Ok(LatePolicy::Publish));
+        assert_eq!("latest".parse(), Ok(LatePolicy::Latest));
+        assert_eq!(
+            "drop:50ms".parse(),
+            Ok(LatePolicy::Drop(Duration::from_millis(50)))
+        );
+        assert!("drop".parse::<LatePolicy>().is_err());
+        assert!("drop:-1".parse::<LatePolicy>().is_err());
+
+        assert_eq!(
+            "/radar/targets=drop:0.1".parse(),
+            Ok(TopicLatePolicy {
+                topic: "/radar/targets".to_owned(),
+                policy: LatePolicy::Drop(Duration::from_millis(100)),
+            })
+        );
+        assert!("latest".parse::<TopicLatePolicy>().is_err());
+        assert!("=latest".parse::<TopicLatePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_drops() {
+        let lag = Duration::from_millis(30);
+        assert!(!LatePolicy::Publish.drops(lag, true));
+        assert!(LatePolicy::Drop(Duration::from_millis(20)).drops(lag, false));
+        assert!(!LatePolicy::Drop(Duration::from_millis(40)).drops(lag, true));
+        assert!(LatePolicy::Latest.drops(lag, true));
+        assert!(!LatePolicy::Latest.drops(lag, false));
+
+        let counters = LateCounters::default();
+        counters.record("/lidar/points", false);
+        counters.record("/lidar/points", true);
+        assert_eq!(
+            counters.snapshot()["/lidar/points"],
+            LateCount {
+                late: 2,
+                dropped: 1
+            }
+        );
+    }
+}
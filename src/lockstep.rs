@@ -0,0 +1,209 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lockstep replay: wait for downstream consumers after every message.
+//!
+//! Consumers acknowledge each message they have finished processing by
+//! publishing their name (or anything at all, when no names are configured)
+//! on `--lockstep-ack-topic`. After publishing a message on one of
+//! `--lockstep-topics`, replay waits until every consumer named in
+//! `--lockstep-consumers` has acknowledged it, or for a single
+//! acknowledgement when none are named. Acknowledgements that arrive before
+//! a message is published belong to an earlier one and are discarded.
+//!
+//! A camera message is decoded on its stream's worker, so replay first waits
+//! for that frame's DMA, image and camera_info outputs to be published, and
+//! skips the acknowledgement wait when decoding produced no frame. The wait
+//! gives up after `--lockstep-timeout`, 30 s by default.
+//!
+//! Lockstep replays as fast as the consumers allow, so it implies `--afap`.
+
+use crate::playback::WAIT_POLL;
+use log::{debug, info, warn};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+use zenoh::{pubsub::Subscriber, Session, Wait};
+
+pub struct Lockstep {
+    topic: String,
+    consumers: Vec<String>,
+    timeout: Option<Duration>,
+    acks: Receiver<String>,
+    /// Feeds `acks`; `None` in tests, which send acknowledgements directly.
+    _subscriber: Option<Subscriber<()>>,
+}
+
+impl Lockstep {
+    /// Subscribe to acknowledgements on `topic`.
+    pub fn declare(
+        session: &Session,
+        topic: &str,
+        consumers: Vec<String>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, zenoh::Error> {
+        if consumers.is_empty() {
+            info!("Lockstep replay: waiting for an acknowledgement on {topic} after each message");
+        } else {
+            info!("Lockstep replay: waiting for {consumers:?} to acknowledge on {topic} after each message");
+        }
+        let (sender, acks) = channel();
+        let subscriber = session
+            .declare_subscriber(topic.to_owned())
+            .callback(move |sample| {
+                let consumer = sample.payload().try_to_string().unwrap_or_default();
+                let _ = sender.send(consumer.trim().to_owned());
+            })
+            .wait()?;
+        Ok(Lockstep {
+            _subscriber: Some(subscriber),
+            ..Lockstep::new(topic, consumers, timeout, acks)
+        })
+    }
+
+    fn new(
+        topic: &str,
+        consumers: Vec<String>,
+        timeout: Option<Duration>,
+        acks: Receiver<String>,
+    ) -> Self {
+        Lockstep {
+            topic: topic.to_owned(),
+            consumers,
+            timeout,
+            acks,
+            _subscriber: None,
+        }
+    }
+
+    /// Discard acknowledgements of earlier messages. Called before
+    /// publishing the message to be acknowledged.
+    pub fn clear(&self) {
+        while self.acks.try_recv().is_ok() {}
+    }
+
+    /// Wait until the message just published has been acknowledged. Returns
+    /// false if `run` was cleared while waiting.
+    pub fn wait(&self, run: &AtomicBool) -> bool {
+        let mut pending: HashSet<&str> = self.consumers.iter().map(String::as_str).collect();
+        let start = Instant::now();
+        loop {
+            if !run.load(Ordering::Relaxed) {
+                return false;
+            }
+            let mut poll = WAIT_POLL;
+            if let Some(timeout) = self.timeout {
+                let left = timeout.saturating_sub(start.elapsed());
+                if left.is_zero() {
+                    if pending.is_empty() {
+                        warn!(
+                            "No acknowledgement on {} within {timeout:?}, continuing",
+                            self.topic
+                        );
+                    } else {
+                        warn!(
+                            "No acknowledgement from {pending:?} on {} within {timeout:?}, continuing",
+                            self.topic
+                        );
+                    }
+                    return true;
+                }
+                poll = poll.min(left);
+            }
+            match self.acks.recv_timeout(poll) {
+                Ok(consumer) => {
+                    if self.consumers.is_empty() || pending.remove(consumer.as_str()) {
+                        if pending.is_empty() {
+                            return true;
+                        }
+                    } else {
+                        debug!(
+                            "Ignoring acknowledgement from '{consumer}' on {}",
+                            self.topic
+                        );
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lockstep;
+    use std::{
+        sync::{atomic::AtomicBool, mpsc::channel},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn consumers() -> Vec<String> {
+        vec!["tracker".to_owned(), "logger".to_owned()]
+    }
+
+    #[test]
+    fn test_wait_for_all_consumers() {
+        let (acks, receiver) = channel();
+        let lockstep = Lockstep::new("rt/replay/ack", consumers(), None, receiver);
+        let run = AtomicBool::new(true);
+        let consumer = thread::spawn(move || {
+            for name in ["tracker", "viewer", "tracker", "logger"] {
+                thread::sleep(Duration::from_millis(10));
+                acks.send(name.to_owned()).unwrap();
+            }
+            acks
+        });
+        let start = Instant::now();
+        assert!(lockstep.wait(&run));
+        // Returned on the logger's acknowledgement, the last of the four.
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        consumer.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let (acks, receiver) = channel();
+        let lockstep = Lockstep::new("rt/replay/ack", consumers(), Some(TIMEOUT), receiver);
+        let run = AtomicBool::new(true);
+        acks.send("tracker".to_owned()).unwrap();
+        let start = Instant::now();
+        // The logger never acknowledges: replay carries on after the
+        // timeout.
+        assert!(lockstep.wait(&run));
+        assert!(start.elapsed() >= TIMEOUT);
+
+        // Stopping replay ends the wait.
+        let lockstep = Lockstep::new("rt/replay/ack", consumers(), None, channel().1);
+        assert!(!lockstep.wait(&AtomicBool::new(false)));
+    }
+
+    #[test]
+    fn test_clear_discards_stale_acks() {
+        let (acks, receiver) = channel();
+        let lockstep = Lockstep::new("rt/replay/ack", Vec::new(), Some(TIMEOUT), receiver);
+        let run = AtomicBool::new(true);
+        // Acknowledgements of a message published before a seek arrive
+        // after it; clearing before the next message discards them, so the
+        // wait runs into the timeout instead of returning at once.
+        acks.send("tracker".to_owned()).unwrap();
+        acks.send("logger".to_owned()).unwrap();
+        lockstep.clear();
+        let start = Instant::now();
+        assert!(lockstep.wait(&run));
+        assert!(start.elapsed() >= TIMEOUT);
+
+        acks.send("tracker".to_owned()).unwrap();
+        let start = Instant::now();
+        assert!(lockstep.wait(&run));
+        assert!(start.elapsed() < TIMEOUT);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loop count, gap and marker.
+//!
+//! Replay starts over at the end of the recording until `--loops` passes
+//! have been played (one with `--one-shot`, forever by default), pausing
+//! `--loop-gap` between passes. With `--loop-topic` each pass starts with a
+//! `std_msgs/Header` marker stamped with the pass's start time and carrying
+//! the loop number as its frame_id, so consumers can reset their state.
+
+use crate::playback::WAIT_POLL;
+use edgefirst_schemas::{builtin_interfaces::Time, std_msgs::Header};
+use log::error;
+use std::{
+    error::Error,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use zenoh::{
+    bytes::{Encoding, ZBytes},
+    Session, Wait,
+};
+
+const HEADER_SCHEMA: &str = "std_msgs/msg/Header";
+
+/// How many passes to play and the pause between them.
+#[derive(Debug, Clone, Copy)]
+pub struct Looping {
+    /// Passes to play; `None` loops forever.
+    loops: Option<u64>,
+    gap: Duration,
+}
+
+impl Looping {
+    pub fn new(one_shot: bool, loops: Option<u64>, gap: Duration) -> Self {
+        Looping {
+            loops: if one_shot { Some(1) } else { loops },
+            gap,
+        }
+    }
+
+    /// Whether replay ends after pass `iteration`, counted from 1.
+    pub fn is_last(&self, iteration: u64) -> bool {
+        self.loops.is_some_and(|n| iteration >= n)
+    }
+
+    /// Sit out the pause between loops, returning early on Ctrl-C.
+    pub fn wait_gap(&self, run: &AtomicBool) {
+        let end = Instant::now() + self.gap;
+        while run.load(Ordering::Relaxed) {
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            thread::sleep(left.min(WAIT_POLL));
+        }
+    }
+}
+
+/// Encode the marker for the start of loop `iteration`.
+fn marker(iteration: u64, stamp: Time) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut buf = Vec::new();
+    Header::builder()
+        .stamp(stamp)
+        .frame_id(iteration.to_string())
+        .encode_into_vec(&mut buf)?;
+    Ok(buf)
+}
+
+/// Publish the marker for the start of loop `iteration`.
+pub fn publish_marker(session: &Session, topic: &str, iteration: u64, stamp: Time) {
+    let buf = match marker(iteration, stamp) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Could not encode loop marker: {:?}", e);
+            return;
+        }
+    };
+    let enc = Encoding::APPLICATION_CDR.with_schema(HEADER_SCHEMA);
+    if let Err(e) = session.put(topic, ZBytes::from(buf)).encoding(enc).wait() {
+        error!("Error sending loop marker on {}: {:?}", topic, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{marker, Looping};
+    use edgefirst_schemas::{builtin_interfaces::Time, std_msgs::Header};
+    use std::{
+        sync::atomic::AtomicBool,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_loop_count() {
+        let once = Looping::new(true, None, Duration::ZERO);
+        assert!(once.is_last(1));
+        let three = Looping::new(false, Some(3), Duration::ZERO);
+        assert!(!three.is_last(1) && !three.is_last(2));
+        assert!(three.is_last(3));
+        let forever = Looping::new(false, None, Duration::ZERO);
+        assert!(!forever.is_last(1_000_000));
+    }
+
+    #[test]
+    fn test_loop_gap() {
+        let gap = Duration::from_millis(50);
+        let looping = Looping::new(false, None, gap);
+        let start = Instant::now();
+        looping.wait_gap(&AtomicBool::new(true));
+        assert!(start.elapsed() >= gap);
+
+        // Ctrl-C cuts the gap short.
+        let looping = Looping::new(false, None, Duration::from_secs(60));
+        let start = Instant::now();
+        looping.wait_gap(&AtomicBool::new(false));
+        assert!(start.elapsed() < gap);
+    }
+
+    #[test]
+    fn test_marker() {
+        let stamp = Time::from_nanos(1_700_000_000_500_000_000);
+        let buf = marker(3, stamp).unwrap();
+        let header = Header::from_cdr(buf.as_slice()).unwrap();
+        assert_eq!(header.frame_id(), "3");
+        assert_eq!(header.stamp(), stamp);
+    }
+}
@@ -4,41 +4,69 @@
 //! EdgeFirst MCAP replay service.
 
 mod args;
+mod camera;
+mod camera_info;
+mod control;
+mod de265;
+mod decode_worker;
 mod image_publish;
+mod info;
+mod late;
+mod lockstep;
+mod looping;
+mod playback;
+mod playlist;
+mod remap;
+mod restamp;
 mod services;
+mod sim_clock;
+mod source;
+mod status;
+mod timing;
 mod video_decode;
 
 use args::Args;
-use clap::Parser;
+use camera::{CameraSource, CameraStream, LoopReset};
+use camera_info::CameraInfoPublisher;
+use decode_worker::{DecodeWorker, Job};
 use edgefirst_hal::tensor::TensorDyn;
 #[allow(deprecated)]
 use edgefirst_schemas::edgefirst_msgs::DmaBuffer;
 use edgefirst_schemas::{
     builtin_interfaces::Time, foxglove_msgs::FoxgloveCompressedVideo, sensor_msgs::CompressedImage,
 };
-use image_publish::HalImagePublisher;
+use image_publish::{
+    HalImagePublisher, ImageGeometry, ImageOptions, ImageOutput, JpegOptions, Layout, Orientation,
+};
+use info::{FileInfo, ListFormat};
+use late::{LateCounters, LatePolicy, LATE_AFTER};
+use lockstep::Lockstep;
 use log::{debug, error, info, warn};
+use looping::Looping;
 use mcap::Message;
 use memmap2::Mmap;
+use playback::{Playback, Wake};
+use playlist::Playlist;
+use remap::{TopicMapper, TopicPrefix};
 use services::ServiceHandler;
-use std::thread::sleep;
+use source::TimeRange;
+use status::StatusPublisher;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
-    fs,
-    os::fd::AsRawFd,
-    path::Path,
+    os::fd::{AsRawFd, RawFd},
     process,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
 };
 use tracing::{info_span, instrument};
-use tracing_subscriber::{layer::SubscriberExt as _, Layer as _, Registry};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt as _, Layer as _, Registry,
+};
 use tracy_client::{frame_mark, secondary_frame_mark};
-use video_decode::{JpegStream, VideoDecoder};
+use video_decode::{CompressedVideoDecoder, DecoderBackend, JpegStream, VideoCodec};
 use videostream::frame::Frame;
 use zenoh::{
     bytes::{Encoding, ZBytes},
@@ -49,24 +77,21 @@ use zenoh::{
 const DMA_SCHEMA: &str = "edgefirst_msgs/msg/DmaBuffer";
 const NV12_FOURCC: u32 = u32::from_le_bytes(*b"NV12");
 
-fn map_mcap<P: AsRef<Path>>(p: P) -> Result<Mmap, String> {
-    let fd = match fs::File::open(p.as_ref()) {
-        Ok(v) => v,
-        Err(e) => return Err(format!("Couldn't open MCAP file: {:#?} {e}", p.as_ref())),
-    };
-    match unsafe { Mmap::map(&fd) } {
-        Ok(v) => Ok(v),
-        Err(e) => Err(format!("Couldn't map MCAP file: {e}")),
-    }
+/// Schema name of a channel, empty when it has none.
+fn schema_name(channel: &mcap::Channel) -> String {
+    channel
+        .schema
+        .as_ref()
+        .map_or_else(String::new, |s| s.name.clone())
 }
 
-fn get_topics(mapped: &Mmap) -> HashSet<String> {
-    let mut topics = HashSet::new();
+/// Recorded topics and their schema names.
+fn get_topics(mapped: &Mmap) -> HashMap<String, String> {
+    let mut topics = HashMap::new();
 
     if let Ok(Some(summary)) = mcap::Summary::read(mapped) {
         for c in summary.channels.values() {
-            let topic = c.topic.clone();
-            topics.insert(topic);
+            topics.insert(c.topic.clone(), schema_name(c));
         }
 
         if !topics.is_empty() {
@@ -90,18 +115,35 @@ fn get_topics(mapped: &Mmap) -> HashSet<String> {
                 continue;
             }
         };
-        let topic = message.channel.topic.clone();
-        topics.insert(topic);
+        topics
+            .entry(message.channel.topic.clone())
+            .or_insert_with(|| schema_name(&message.channel));
     }
     topics
 }
 
+/// Expand `--topics` / `--ignore-topics` against the topic prefix.
+fn topic_key_exprs(
+    topics: &[Option<String>],
+    prefix: &TopicPrefix,
+) -> Result<Vec<OwnedKeyExpr>, String> {
+    topics
+        .iter()
+        .flatten()
+        .map(|t| {
+            OwnedKeyExpr::autocanonize(prefix.expand(t))
+                .map_err(|_| format!("Could not parse topic: {t}"))
+        })
+        .collect()
+}
+
 fn filter_topic(
     include_topics: &[OwnedKeyExpr],
     ignore_topics: &[OwnedKeyExpr],
+    prefix: &TopicPrefix,
     mcap_topic: &str,
 ) -> bool {
-    let topic = "rt".to_owned() + mcap_topic;
+    let topic = prefix.key(mcap_topic);
     let topic = KeyExpr::autocanonize(topic).unwrap_or_else(|_| {
         panic!("mcap topic {mcap_topic} cannot be converted to valid zenoh topic")
     });
@@ -124,15 +166,20 @@ fn filter_topic(
     to_publish
 }
 
-const INIT_TIME_VAL: u64 = 0;
-
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::load();
 
     let _tracy = args.tracy.then(tracy_client::Client::start);
 
+    // --list output goes to stdout, so keep it parseable by logging to
+    // stderr instead.
+    let log_writer = match args.list {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(std::io::stdout),
+    };
     let stdout_log = tracing_subscriber::fmt::layer()
         .pretty()
+        .with_writer(log_writer)
         .with_filter(args.rust_log);
 
     let journald = match tracing_journald::layer() {
@@ -152,24 +199,49 @@ fn main() {
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     tracing_log::LogTracer::init().unwrap();
 
-    let mapped = match map_mcap(&args.mcap) {
+    let playlist = match Playlist::open(&args.mcap, &args.time_offset, args.collapse_gaps) {
         Ok(v) => v,
         Err(e) => {
             error!("Could not open mcap file: {:?}", e);
             return;
         }
     };
-    info!("Opened MCAP file {:?}", args.mcap);
+    for r in &playlist.recordings {
+        info!("Opened MCAP file {:?}", r.path);
+    }
+    let mcap_topics: HashMap<String, String> = playlist
+        .recordings
+        .iter()
+        .flat_map(|r| get_topics(&r.mapped))
+        .collect();
 
     if args.list {
-        let topics = get_topics(&mapped);
-
-        if topics.is_empty() {
-            println!("Did not find any topics in MCAP");
-            return;
+        let mut files = Vec::new();
+        for r in &playlist.recordings {
+            match FileInfo::collect(r, args.scan) {
+                Ok(v) => files.push(v),
+                Err(e) => {
+                    error!("Could not read MCAP file {:?}: {:?}", r.path, e);
+                    process::exit(1);
+                }
+            }
         }
-        for t in topics {
-            println!("{}", t);
+        match args.format {
+            ListFormat::Json => {
+                let files: Vec<_> = files
+                    .iter()
+                    .map(|f| f.to_json(&args.topic_prefix))
+                    .collect();
+                println!("{}", serde_json::json!({ "files": files }));
+            }
+            ListFormat::Text => {
+                for (i, f) in files.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    f.print(&args.topic_prefix);
+                }
+            }
         }
         return;
     }
@@ -183,34 +255,215 @@ fn main() {
     })
     .expect("Error setting Ctrl-C handler");
 
-    let topics: Vec<OwnedKeyExpr> = args.topics.iter().flatten().cloned().collect();
-    let ignore_topics: Vec<OwnedKeyExpr> = args.ignore_topics.iter().flatten().cloned().collect();
-
-    // Hal-backed RGBA image publisher. Lives across replay-loop restarts;
-    // its pre-allocated destination ring and inode-keyed source cache are
-    // never invalidated. Disabled when --camera-image-topic is empty.
-    let mut hal_publisher = if args.camera_image_topic.is_empty() {
-        None
-    } else {
-        Some(HalImagePublisher::new(
-            args.camera_image_topic.clone(),
-            args.camera_image_buffers,
-        ))
+    let prefix = args.topic_prefix.clone();
+    let (topics, ignore_topics) = match (
+        topic_key_exprs(&args.topics, &prefix),
+        topic_key_exprs(&args.ignore_topics, &prefix),
+    ) {
+        (Ok(t), Ok(i)) => (t, i),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{e}");
+            return;
+        }
+    };
+    let lockstep_topics = match topic_key_exprs(&args.lockstep_topics, &prefix) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
     };
 
+    // Resolve the keys replay publishes or serves on through --remap and
+    // --namespace once, before anything captures them.
+    let mapper = TopicMapper::new(prefix.clone(), args.remap.clone(), &args.namespace);
+    for topic in [
+        &mut args.dma_topic,
+        &mut args.camera_image_topic,
+        &mut args.camera_jpeg_topic,
+        &mut args.camera_info_topic,
+        &mut args.control_topic,
+        &mut args.status_topic,
+        &mut args.clock_topic,
+        &mut args.lockstep_ack_topic,
+        &mut args.loop_topic,
+    ] {
+        if topic.is_empty() {
+            continue;
+        }
+        match mapper.map(&prefix.expand(topic)) {
+            Ok(v) => *topic = v,
+            Err(e) => {
+                error!("Invalid topic remapping: {e}");
+                return;
+            }
+        }
+    }
+
+    for stream in &mut args.camera_stream {
+        let outputs = std::iter::once(&mut stream.dma_topic).chain(stream.image_topic.as_mut());
+        for topic in outputs {
+            match mapper.map(&prefix.expand(topic)) {
+                Ok(v) => *topic = v,
+                Err(e) => {
+                    error!("Invalid topic remapping: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
     info!("Publishing topics: {:?}", topics);
     info!("Ignoring topics: {:?}", ignore_topics);
 
-    let topics_to_publish: HashSet<_> = get_topics(&mapped)
-        .into_iter()
-        .filter(|t| filter_topic(&topics, &ignore_topics, t))
+    let topics_to_publish: HashSet<_> = mcap_topics
+        .keys()
+        .filter(|t| filter_topic(&topics, &ignore_topics, &prefix, t))
+        .cloned()
         .collect();
     info!(
         "Found the following topics to publish: {:#?}",
         topics_to_publish
     );
 
-    let service_handler = ServiceHandler::new();
+    let lockstep_waits: HashSet<&str> = topics_to_publish
+        .iter()
+        .filter(|t| filter_topic(&lockstep_topics, &[], &prefix, t))
+        .map(String::as_str)
+        .collect();
+
+    let mut output_keys = HashMap::new();
+    for topic in &topics_to_publish {
+        let key = match mapper.map(&prefix.key(topic)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Invalid topic remapping: {e}");
+                return;
+            }
+        };
+        if key != prefix.key(topic) {
+            info!("Publishing {topic} on {key}");
+        }
+        output_keys.insert(topic.clone(), key);
+    }
+
+    let mut camera_channels: Vec<_> = topics_to_publish
+        .iter()
+        .map(|t| CameraSource {
+            topic: t,
+            key: &output_keys[t],
+            schema: &mcap_topics[t],
+        })
+        .filter(|s| s.schema == camera::VIDEO_SCHEMA || s.schema == camera::JPEG_SCHEMA)
+        .collect();
+    camera_channels.sort_by_key(|s| s.topic);
+    let camera_sources = camera::select(camera_channels, &args.dma_source, &prefix);
+    let calibrations = if args.camera_info_topic.is_empty() {
+        HashMap::new()
+    } else {
+        match camera_info::resolve(
+            &camera_sources,
+            &args.camera_calibration,
+            &playlist.recordings,
+            &prefix,
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Could not load camera calibration: {e}");
+                return;
+            }
+        }
+    };
+    let camera_streams = match camera::streams(
+        &camera_sources,
+        &args.camera_stream,
+        &args.camera_image_orientation,
+        &prefix,
+        &args.dma_topic,
+        &args.camera_image_topic,
+        &args.camera_jpeg_topic,
+        &args.camera_info_topic,
+        ImageOptions {
+            buffers: args.camera_image_buffers,
+            format: args.camera_image_format,
+            geometry: ImageGeometry {
+                size: args.camera_image_size,
+                fit: args.camera_image_fit,
+                roi: args.camera_image_roi,
+                orientation: Orientation {
+                    rotation: args.camera_image_rotation,
+                    flip: args.camera_image_flip,
+                },
+            },
+            output: ImageOutput::Raw,
+        },
+        JpegOptions {
+            quality: args.camera_jpeg_quality,
+            downscale: args.camera_jpeg_downscale,
+        },
+        &calibrations,
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Invalid camera outputs: {e}");
+            return;
+        }
+    };
+    for publisher in camera_streams
+        .values()
+        .filter_map(|s| s.jpeg_publisher.as_ref())
+    {
+        if output_keys.values().any(|k| k == publisher.topic()) {
+            warn!(
+                "JPEG output {} is also a replayed topic; both will publish on it",
+                publisher.topic()
+            );
+        }
+    }
+    for c in &args.camera_stream {
+        if !camera_sources
+            .iter()
+            .any(|s| prefix.expand(&c.source) == prefix.key(s.topic))
+        {
+            warn!("Camera stream {} is not a decoded camera topic", c.source);
+        }
+    }
+    let late_policies: HashMap<&str, LatePolicy> = topics_to_publish
+        .iter()
+        .map(|t| {
+            let policy = args
+                .topic_late_policy
+                .iter()
+                .find(|p| prefix.expand(&p.topic) == prefix.key(t))
+                .map_or(args.late_policy, |p| p.policy);
+            if policy != args.late_policy {
+                info!("Late policy for {t}: {policy:?}");
+            }
+            (t.as_str(), policy)
+        })
+        .collect();
+    for p in &args.topic_late_policy {
+        if !topics_to_publish
+            .iter()
+            .any(|t| prefix.expand(&p.topic) == prefix.key(t))
+        {
+            warn!("Late policy topic {} is not a replayed topic", p.topic);
+        }
+    }
+    let late_counters = Arc::new(LateCounters::default());
+    for o in &args.camera_image_orientation {
+        if !camera_sources
+            .iter()
+            .any(|s| prefix.expand(&o.source) == prefix.key(s.topic))
+        {
+            warn!(
+                "Camera orientation {} is not a decoded camera topic",
+                o.source
+            );
+        }
+    }
+
+    let service_handler = ServiceHandler::new(prefix.clone());
     if args.system {
         info!("Stopping system services before replay");
         service_handler.stop_services(&topics_to_publish);
@@ -218,44 +471,197 @@ fn main() {
         info!("Keeping system services running");
     }
 
+    let file_start = playlist.start();
+    let range = if args.start.is_none() && args.end.is_none() {
+        TimeRange::ALL
+    } else {
+        let range = TimeRange {
+            start: args.start.map_or(0, |t| t.resolve(file_start)),
+            end: args.end.map_or(u64::MAX, |t| t.resolve(file_start)),
+        };
+        if range.start >= range.end {
+            error!(
+                "Replay start {} is not before replay end {}",
+                range.start, range.end
+            );
+            return;
+        }
+        info!(
+            "Replaying log time range {}..{} (recording starts at {})",
+            range.start, range.end, file_start
+        );
+        range
+    };
+
     let session = zenoh::open(args.clone()).wait().unwrap();
     let src_pid = process::id();
 
-    loop {
-        let msg_stream = match mcap::MessageStream::new(&mapped) {
-            Ok(v) => v,
+    let mut camera_workers = HashMap::new();
+    for (topic, mut stream) in camera_streams {
+        let session = session.clone();
+        let decoder = args.decoder;
+        let tracy = args.tracy;
+        let worker = DecodeWorker::spawn(&topic, args.decode_queue, move |job| match job {
+            Job::Video { data, publish } => {
+                let published =
+                    stream_video(&data, publish, &mut stream, src_pid, decoder, &session);
+                tracy.then(|| secondary_frame_mark!("video"));
+                published
+            }
+            Job::Jpeg(data) => {
+                let published = stream_jpeg(&data, &mut stream, src_pid, &session);
+                tracy.then(|| secondary_frame_mark!("jpeg"));
+                published
+            }
+            Job::Reset(reset) => {
+                stream.reset(reset);
+                false
+            }
+        });
+        match worker {
+            Ok(v) => camera_workers.insert(topic, v),
             Err(e) => {
-                error!("Could not parse mcap file: {:?}", e);
+                error!("Could not start decode thread for {topic}: {e}");
                 return;
             }
         };
-        info!("Parsed MCAP file {:?}", args.mcap);
+    }
 
-        let mut has_h264 = false;
+    let lockstep = if args.lockstep_ack_topic.is_empty() {
+        None
+    } else {
+        match Lockstep::declare(
+            &session,
+            &args.lockstep_ack_topic,
+            args.lockstep_consumers.clone(),
+            (!args.lockstep_timeout.is_zero()).then_some(args.lockstep_timeout),
+        ) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                error!(
+                    "Could not declare lockstep acknowledgement subscriber: {:?}",
+                    e
+                );
+                return;
+            }
+        }
+    };
+    let afap = args.afap || lockstep.is_some();
+    if afap {
+        info!("Replaying as fast as possible");
+    }
+    let playback = Arc::new(Playback::new(args.replay_speed, afap));
+    let _control = if args.control_topic.is_empty() {
+        None
+    } else {
+        match control::declare(
+            &session,
+            &args.control_topic,
+            playback.clone(),
+            file_start,
+            range,
+        ) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                error!("Could not declare playback control queryable: {:?}", e);
+                return;
+            }
+        }
+    };
 
-        let msg_stream = msg_stream.filter(|message| {
-            let message = match message {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("Could not parse mcap message: {:?}", e);
-                    return false;
-                }
-            };
-            topics_to_publish.contains(&message.channel.topic)
-        });
+    let status = if args.status_topic.is_empty() {
+        None
+    } else {
+        let extent = TimeRange {
+            start: range.start.max(file_start),
+            end: playlist
+                .end()
+                .map_or(range.end, |t| range.end.min(t.saturating_add(1))),
+        };
+        let status = Arc::new(StatusPublisher::new(
+            session.clone(),
+            args.status_topic.clone(),
+            playlist.file_starts(),
+            extent,
+            late_counters.clone(),
+        ));
+        status
+            .clone()
+            .spawn(playback.clone(), args.status_interval, run.clone());
+        Some(status)
+    };
+    if args.clock {
+        sim_clock::spawn(
+            session.clone(),
+            args.clock_topic.clone(),
+            args.clock_rate,
+            playback.clone(),
+            run.clone(),
+        );
+    }
 
-        let mut first_msg_time = INIT_TIME_VAL;
-        let mut start = Instant::now();
+    let status_event = |event: &str| {
+        if let Some(status) = &status {
+            status.event(event, &playback.status());
+        }
+    };
+    let log_late = || {
+        for (topic, count) in late_counters.snapshot() {
+            info!(
+                "Late messages on {topic}: {} ({} dropped)",
+                count.late, count.dropped
+            );
+        }
+    };
 
-        let mut video_decoder: Option<VideoDecoder> = None;
-        let mut jpeg_stream: Option<JpegStream> = None;
+    let looping = Looping::new(args.one_shot, args.loops, args.loop_gap);
+    // Log time a seek request asked the next pass to start from.
+    let mut seek_to: Option<u64> = None;
+    let mut iteration = 0;
 
-        for message in msg_stream {
-            if !run.load(Ordering::Relaxed) {
-                return;
-            }
+    loop {
+        if !run.load(Ordering::Relaxed) {
+            status_event("stopped");
+            return;
+        }
+
+        // A seek restarts the message stream within the current loop.
+        let new_loop = seek_to.is_none();
+        if new_loop {
+            iteration = playback.start_loop();
+            status_event("loop_start");
+        }
+        let mut marker = (new_loop && !args.loop_topic.is_empty()).then_some(iteration);
+
+        let pass_range = TimeRange {
+            start: seek_to.unwrap_or(range.start),
+            ..range
+        };
+        // Anchor the clock at the seek point when one was requested so the
+        // gap before the first matching message is preserved.
+        let anchor = seek_to.take().or(args.start.map(|_| range.start));
 
-            let message = match message {
+        let mut msg_stream = playlist.messages(&topics_to_publish, pass_range, args.timing);
+        // Messages read ahead to find out whether a late message has been
+        // superseded, replayed before reading on.
+        let mut ahead = VecDeque::new();
+
+        let mut anchored = false;
+
+        let reset = if new_loop {
+            args.loop_reset
+        } else {
+            LoopReset::Decoders
+        };
+        for worker in camera_workers.values() {
+            worker.reset(reset);
+        }
+
+        loop {
+            let Some(message) = ahead.pop_front().or_else(|| msg_stream.next()) else {
+                break;
+            };
+            let mut message = match message {
                 Ok(v) => v,
                 Err(e) => {
                     error!("Could not parse mcap message: {:?}", e);
@@ -263,16 +669,30 @@ fn main() {
                 }
             };
 
-            if first_msg_time == INIT_TIME_VAL {
-                start = Instant::now();
-                first_msg_time = message.log_time;
-            } else {
-                let dur = Duration::from_nanos(
-                    ((message.log_time - first_msg_time) as f64 / args.replay_speed) as u64,
-                )
-                .checked_sub(start.elapsed())
-                .unwrap_or_default();
-                sleep(dur);
+            if !anchored {
+                let start = anchor.unwrap_or(message.log_time);
+                playback.anchor(start);
+                anchored = true;
+                if let Some(iteration) = marker.take() {
+                    let stamp = if args.restamp {
+                        playback.restamp(start)
+                    } else {
+                        Time::from_nanos(start)
+                    };
+                    looping::publish_marker(&session, &args.loop_topic, iteration, stamp);
+                }
+            }
+            match playback.wait_until(message.log_time, &run) {
+                Wake::Publish => (),
+                Wake::Seek(t) => {
+                    seek_to = Some(t);
+                    break;
+                }
+                Wake::Stop => {
+                    log_late();
+                    status_event("stopped");
+                    return;
+                }
             }
 
             let schema = match &message.channel.schema {
@@ -287,143 +707,271 @@ fn main() {
                 continue;
             }
 
-            if schema == "foxglove_msgs/msg/CompressedVideo" {
-                has_h264 = true;
-                stream_h264(
-                    &message,
-                    &mut video_decoder,
-                    src_pid,
-                    &args,
-                    &session,
-                    hal_publisher.as_mut(),
-                );
-                args.tracy.then(|| secondary_frame_mark!("h264"));
+            let topic = &message.channel.topic;
+            let progress = playback.status();
+            if progress.lag > LATE_AFTER {
+                let policy = late_policies[topic.as_str()];
+                let superseded = policy == LatePolicy::Latest && {
+                    // Everything up to the clock's position is already due;
+                    // read it in to see whether a newer message on this
+                    // topic is among it.
+                    let due = |m: &Result<Message, _>| {
+                        m.as_ref().is_ok_and(|m| m.log_time <= progress.log_time)
+                    };
+                    while ahead.back().is_none_or(due) {
+                        match msg_stream.next() {
+                            Some(v) => ahead.push_back(v),
+                            None => break,
+                        }
+                    }
+                    ahead
+                        .iter()
+                        .flatten()
+                        .any(|m| &m.channel.topic == topic && m.log_time <= progress.log_time)
+                };
+                let dropped = policy.drops(progress.lag, superseded);
+                late_counters.record(topic, dropped);
+                if dropped {
+                    debug!("Dropping {topic} message {:?} late", progress.lag);
+                    // Later frames depend on this one, so it is decoded
+                    // without publishing.
+                    if schema == camera::VIDEO_SCHEMA {
+                        if let Some(worker) = camera_workers.get(topic) {
+                            worker.send(Job::Video {
+                                data: message.data.to_vec(),
+                                publish: false,
+                            });
+                        }
+                    }
+                    continue;
+                }
+                debug!("Publishing {topic} message {:?} late", progress.lag);
             }
 
-            // we don't use jpeg for DMA buffer when h264 is present
-            if !has_h264 && schema == "sensor_msgs/msg/CompressedImage" {
-                stream_jpeg(
-                    &message,
-                    &mut jpeg_stream,
-                    src_pid,
-                    &args,
-                    &session,
-                    hal_publisher.as_mut(),
-                );
-                args.tracy.then(|| secondary_frame_mark!("jpeg"));
+            // Restamp before decoding so the DmaBuffer and Image messages
+            // derived from this one inherit the rewritten stamp.
+            if args.restamp && restamp::has_stamp(&schema) {
+                let stamp = playback.restamp(message.log_time);
+                if let Err(e) = restamp::set_stamp(message.data.to_mut(), stamp) {
+                    warn!(
+                        "Could not restamp message on {}: {:?}",
+                        message.channel.topic, e
+                    );
+                }
             }
 
+            let lockstep = lockstep
+                .as_ref()
+                .filter(|_| lockstep_waits.contains(message.channel.topic.as_str()));
+            if let Some(lockstep) = lockstep {
+                lockstep.clear();
+            }
+
+            // Decoding runs on the stream's own thread so the messages
+            // behind this one keep their schedule. In lockstep the frame is
+            // tracked so the wait below covers its decoded outputs.
+            let decoded = camera_workers.get(&message.channel.topic).map(|worker| {
+                let data = message.data.to_vec();
+                let job = if schema == camera::VIDEO_SCHEMA {
+                    Job::Video {
+                        data,
+                        publish: true,
+                    }
+                } else {
+                    Job::Jpeg(data)
+                };
+                match lockstep {
+                    Some(_) => Some(worker.send_tracked(job)),
+                    None => {
+                        worker.send(job);
+                        None
+                    }
+                }
+            });
+
             info_span!("publish").in_scope(|| {
+                let key = &output_keys[&message.channel.topic];
                 let msg = ZBytes::from(message.data.as_ref());
                 let enc = Encoding::APPLICATION_CDR.with_schema(schema.clone());
 
-                match session
-                    .put("rt".to_string() + &message.channel.topic, msg)
-                    .encoding(enc)
-                    .wait()
-                {
+                match session.put(key, msg).encoding(enc).wait() {
                     Ok(_) => (),
                     Err(e) => {
-                        error!(
-                            "Error sending message on {}: {:?}",
-                            "rt".to_string() + &message.channel.topic,
-                            e
-                        )
+                        error!("Error sending message on {}: {:?}", key, e)
                     }
                 }
             });
 
+            if let Some(lockstep) = lockstep {
+                // Consumers of the decoded outputs have nothing to
+                // acknowledge for a frame that published none, e.g. before
+                // the first keyframe.
+                let published = decoded.flatten().is_none_or(|done| done.wait(&run));
+                if !published && run.load(Ordering::Relaxed) {
+                    debug!("{topic} message decoded to no frame, not waiting for consumers");
+                } else if !lockstep.wait(&run) {
+                    log_late();
+                    status_event("stopped");
+                    return;
+                }
+            }
+
             args.tracy.then(frame_mark);
         }
 
-        if args.one_shot {
+        if let Some(t) = seek_to {
+            // Decoders are recreated for the new pass so decoding restarts
+            // cleanly from the next keyframe after the seek point.
+            info!("Seeking to log time {t}");
+            continue;
+        }
+        log_late();
+        if looping.is_last(iteration) {
+            status_event("end");
             break;
         }
         info!("Replay finished, starting over...");
+        looping.wait_gap(&run);
     }
 }
 
 #[instrument(skip_all)]
-fn stream_h264(
-    message: &Message,
-    video_decoder: &mut Option<VideoDecoder>,
+fn stream_video(
+    data: &[u8],
+    publish: bool,
+    stream: &mut CameraStream,
     src_pid: u32,
-    args: &Args,
+    decoder: DecoderBackend,
     session: &Session,
-    hal_publisher: Option<&mut HalImagePublisher>,
-) {
-    let video = match FoxgloveCompressedVideo::<&[u8]>::from_cdr(&message.data) {
+) -> bool {
+    let CameraStream {
+        dma_topic,
+        video: video_decoder,
+        hal_publisher,
+        jpeg_publisher,
+        camera_info,
+        ..
+    } = stream;
+    let video = match FoxgloveCompressedVideo::<&[u8]>::from_cdr(data) {
         Ok(v) => v,
         Err(e) => {
             error!("Could not deserialize CompressedVideo message: {:?}", e);
-            return;
+            return false;
         }
     };
-    if video.format() != "h264" {
+    let Some(codec) = VideoCodec::from_format(video.format()) else {
         error!("Unsupported CompressedVideo format {}", video.format());
-        return;
-    }
+        return false;
+    };
 
+    if video_decoder.as_ref().is_some_and(|d| d.codec() != codec) {
+        info!("CompressedVideo format changed to {codec}, reopening decoder");
+        *video_decoder = None;
+        for publisher in hal_publisher.iter_mut().chain(jpeg_publisher.iter_mut()) {
+            publisher.release_sources();
+        }
+    }
     if video_decoder.is_none() {
-        match VideoDecoder::new() {
+        match CompressedVideoDecoder::new(codec, decoder) {
             Ok(v) => video_decoder.insert(v),
             Err(e) => {
                 error!("Could not open video decoder: {:?}", e);
-                return;
+                return false;
             }
         };
     }
-    let video_decoder = video_decoder.as_mut().unwrap();
+    let stamp = video.stamp();
+    let frame_id = video.frame_id();
+
+    let video_decoder = match video_decoder.as_mut().unwrap() {
+        CompressedVideoDecoder::Hardware(v) => v,
+        CompressedVideoDecoder::Software(decoder) => {
+            let frame = match decoder.decode_msg(video.data()) {
+                Ok(Some(f)) if publish => f,
+                Ok(_) => return false,
+                Err(e) => {
+                    error!("Could not decode video message: {:?}", e);
+                    return false;
+                }
+            };
+            let tensor = &frame.tensor;
+            if let Err(e) = publish_tensor_dma(
+                tensor,
+                frame.fd(),
+                stamp,
+                frame_id,
+                src_pid,
+                dma_topic,
+                session,
+            ) {
+                error!("Failed to publish dma message: {:?}", e);
+            }
+            let vw = tensor.width().unwrap_or(0) as u32;
+            let vh = tensor.height().unwrap_or(0) as u32;
+            for publisher in hal_publisher.iter_mut().chain(jpeg_publisher.iter_mut()) {
+                if let Err(e) =
+                    publisher.publish_from_tensor(tensor, vw, vh, stamp, frame_id, session)
+                {
+                    warn!("hal image publish failed: {:?}", e);
+                }
+            }
+            publish_camera_info(camera_info, hal_publisher, stamp, frame_id, vw, vh, session);
+            return true;
+        }
+    };
 
-    let frame = match video_decoder.decode_h264_msg(video.data()) {
-        Ok(Some(f)) => f,
-        Ok(None) => return,
+    let frame = match video_decoder.decode_msg(video.data()) {
+        Ok(Some(f)) if publish => f,
+        Ok(_) => return false,
         Err(e) => {
             error!("Could not decode video message: {:?}", e);
-            return;
+            return false;
         }
     };
 
-    let stamp = video.stamp();
-    let frame_id = video.frame_id();
-
-    if let Err(e) = publish_frame_dma(&frame, stamp, frame_id, src_pid, &args.dma_topic, session) {
+    if let Err(e) = publish_frame_dma(&frame, stamp, frame_id, src_pid, dma_topic, session) {
         error!("Failed to publish dma message: {:?}", e);
     }
 
-    if let Some(publisher) = hal_publisher {
-        let (vw, vh) = match video_decoder.crop() {
-            Ok(c) => (c.width() as u32, c.height() as u32),
-            Err(e) => {
-                warn!("hal publish skipped — decoder crop unavailable: {:?}", e);
-                return;
-            }
-        };
+    if hal_publisher.is_none() && jpeg_publisher.is_none() && camera_info.is_none() {
+        return true;
+    }
+    let (vw, vh) = match video_decoder.crop() {
+        Ok(c) => (c.width() as u32, c.height() as u32),
+        Err(e) => {
+            warn!("hal publish skipped — decoder crop unavailable: {:?}", e);
+            return true;
+        }
+    };
+    for publisher in hal_publisher.iter_mut().chain(jpeg_publisher.iter_mut()) {
         if let Err(e) = publisher.publish_from_frame(&frame, vw, vh, stamp, frame_id, session) {
             warn!("hal image publish failed: {:?}", e);
         }
     }
+    publish_camera_info(camera_info, hal_publisher, stamp, frame_id, vw, vh, session);
+    true
 }
 
 #[instrument(skip_all)]
-fn stream_jpeg(
-    message: &Message,
-    jpeg_stream: &mut Option<JpegStream>,
-    src_pid: u32,
-    args: &Args,
-    session: &Session,
-    hal_publisher: Option<&mut HalImagePublisher>,
-) {
-    let image = match CompressedImage::<&[u8]>::from_cdr(&message.data) {
+fn stream_jpeg(data: &[u8], stream: &mut CameraStream, src_pid: u32, session: &Session) -> bool {
+    let CameraStream {
+        dma_topic,
+        jpeg: jpeg_stream,
+        hal_publisher,
+        jpeg_publisher,
+        camera_info,
+        ..
+    } = stream;
+    let image = match CompressedImage::<&[u8]>::from_cdr(data) {
         Ok(v) => v,
         Err(e) => {
             error!("Could not deserialize CompressedImage message: {:?}", e);
-            return;
+            return false;
         }
     };
     if image.format() != "jpeg" {
         error!("Unsupported CompressedImage format {}", image.format());
-        return;
+        return false;
     }
 
     if jpeg_stream.is_none() {
@@ -431,7 +979,7 @@ fn stream_jpeg(
             Ok(v) => jpeg_stream.insert(v),
             Err(e) => {
                 error!("Could not open jpeg stream: {:?}", e);
-                return;
+                return false;
             }
         };
     }
@@ -441,24 +989,62 @@ fn stream_jpeg(
         Ok(t) => t,
         Err(e) => {
             error!("Could not decode jpeg message: {:?}", e);
-            return;
+            return false;
         }
     };
 
     let stamp = image.stamp();
     let frame_id = image.frame_id();
 
-    if let Err(e) = publish_tensor_dma(tensor, stamp, frame_id, src_pid, &args.dma_topic, session) {
+    let fd = match tensor.dmabuf() {
+        Ok(fd) => fd.as_raw_fd(),
+        Err(e) => {
+            error!("Failed to publish dma message: {:?}", e);
+            return false;
+        }
+    };
+    if let Err(e) = publish_tensor_dma(tensor, fd, stamp, frame_id, src_pid, dma_topic, session) {
         error!("Failed to publish dma message: {:?}", e);
     }
 
-    if let Some(publisher) = hal_publisher {
-        let vw = tensor.width().unwrap_or(0) as u32;
-        let vh = tensor.height().unwrap_or(0) as u32;
+    let vw = tensor.width().unwrap_or(0) as u32;
+    let vh = tensor.height().unwrap_or(0) as u32;
+    for publisher in hal_publisher.iter_mut().chain(jpeg_publisher.iter_mut()) {
         if let Err(e) = publisher.publish_from_tensor(tensor, vw, vh, stamp, frame_id, session) {
             warn!("hal image publish failed: {:?}", e);
         }
     }
+    publish_camera_info(camera_info, hal_publisher, stamp, frame_id, vw, vh, session);
+    true
+}
+
+/// Publish the stream's calibration for a decoded `width`x`height` frame,
+/// matching the decoded image when the stream publishes one.
+fn publish_camera_info(
+    publisher: &mut Option<CameraInfoPublisher>,
+    image: &Option<HalImagePublisher>,
+    stamp: Time,
+    frame_id: &str,
+    width: u32,
+    height: u32,
+    session: &Session,
+) {
+    let Some(publisher) = publisher else {
+        return;
+    };
+    let layout = match image {
+        Some(image) => match image.layout(width, height) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("camera_info publish skipped: {e}");
+                return;
+            }
+        },
+        None => Layout::full(width, height),
+    };
+    if let Err(e) = publisher.publish(stamp, frame_id, width, height, &layout, session) {
+        warn!("camera_info publish failed: {:?}", e);
+    }
 }
 
 /// Publish a videostream Frame as a `DmaBuffer` carrying decoder-native NV12.
@@ -482,17 +1068,17 @@ fn publish_frame_dma(
     )
 }
 
-/// Publish a hal NV12 dma-buf TensorDyn as a `DmaBuffer`.
+/// Publish a hal NV12 TensorDyn as a `DmaBuffer` referencing `fd`, which
+/// must stay open while receivers may import the buffer.
 fn publish_tensor_dma(
     tensor: &TensorDyn,
+    fd: RawFd,
     stamp: Time,
     frame_id: &str,
     pid: u32,
     topic: &str,
     session: &Session,
 ) -> Result<(), Box<dyn Error>> {
-    let fd_borrow = tensor.dmabuf()?;
-    let fd = fd_borrow.as_raw_fd();
     let width = tensor.width().ok_or("tensor missing width")? as u32;
     let height = tensor.height().ok_or("tensor missing height")? as u32;
     let stride = tensor
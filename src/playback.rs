@@ -0,0 +1,385 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Playback clock and shared pause/seek/speed state for the replay loop.
+//!
+//! The clock maps MCAP log time onto wall time through an anchor pair
+//! `(anchor_log, anchor_wall)` and a speed multiplier. Every state change
+//! (pause, resume, speed change, seek) re-anchors at the current log
+//! position, so deadlines are always computed from a single reference point
+//! rather than accumulated sleeps and never drift.
+//!
+//! With `--afap` the clock does not pace at all: every message is due as
+//! soon as the loop reaches it and the clock's position follows the last
+//! published message. Pause, single-step and seek work as usual.
+
+use edgefirst_schemas::builtin_interfaces::Time;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Upper bound on a single wait so Ctrl-C is noticed while paused, while
+/// waiting out a long gap in the recording or while a lockstep consumer is
+/// busy.
+pub const WAIT_POLL: Duration = Duration::from_millis(100);
+
+/// Maps recording log time to wall time at a given speed.
+#[derive(Debug, Clone)]
+pub struct PlaybackClock {
+    speed: f64,
+    paused: bool,
+    /// As fast as possible: ignore log time and speed.
+    afap: bool,
+    anchor_log: u64,
+    anchor_wall: Instant,
+}
+
+impl PlaybackClock {
+    pub fn new(speed: f64, afap: bool) -> Self {
+        Self {
+            speed,
+            paused: false,
+            afap,
+            anchor_log: 0,
+            anchor_wall: Instant::now(),
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_afap(&self) -> bool {
+        self.afap
+    }
+
+    /// Pin `log_time` to the current wall time.
+    pub fn anchor(&mut self, log_time: u64) {
+        self.anchor_log = log_time;
+        self.anchor_wall = Instant::now();
+    }
+
+    /// Current position in recording log time.
+    pub fn log_now(&self) -> u64 {
+        if self.paused || self.afap {
+            return self.anchor_log;
+        }
+        let elapsed = self.anchor_wall.elapsed().as_secs_f64() * self.speed;
+        self.anchor_log.saturating_add((elapsed * 1e9) as u64)
+    }
+
+    /// Wall time remaining until `log_time` is due; zero if already late.
+    /// Meaningless while paused.
+    pub fn until(&self, log_time: u64) -> Duration {
+        self.scheduled(log_time)
+            .saturating_sub(self.anchor_wall.elapsed())
+    }
+
+    /// How far behind its ideal schedule `log_time` is; zero if on time.
+    pub fn lag(&self, log_time: u64) -> Duration {
+        self.anchor_wall
+            .elapsed()
+            .saturating_sub(self.scheduled(log_time))
+    }
+
+    /// System time (since the Unix epoch) at which `log_time` is due.
+    pub fn wall_time(&self, log_time: u64) -> Duration {
+        let anchor = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_sub(self.anchor_wall.elapsed());
+        anchor + self.scheduled(log_time)
+    }
+
+    /// Wall time after the anchor at which `log_time` is due.
+    fn scheduled(&self, log_time: u64) -> Duration {
+        if self.afap {
+            return self.anchor_wall.elapsed();
+        }
+        let offset = log_time.saturating_sub(self.anchor_log) as f64 / self.speed;
+        Duration::from_nanos(offset as u64)
+    }
+
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.anchor_log = self.log_now();
+            self.paused = true;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.anchor_wall = Instant::now();
+            self.paused = false;
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        let now = self.log_now();
+        self.anchor(now);
+        self.speed = speed;
+    }
+}
+
+/// Snapshot of the playback state.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackStatus {
+    pub paused: bool,
+    pub speed: f64,
+    /// Replaying as fast as possible, ignoring `speed`.
+    pub afap: bool,
+    pub log_time: u64,
+    /// Log time of the most recently published message.
+    pub position: u64,
+    /// How late the most recently published message was against its ideal
+    /// schedule.
+    pub lag: Duration,
+    /// Current loop iteration, starting at 1; 0 before replay starts.
+    pub iteration: u64,
+}
+
+/// Outcome of waiting for a message's scheduled time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// The message is due; publish it.
+    Publish,
+    /// A seek was requested; restart the message stream at this log time.
+    Seek(u64),
+    /// Ctrl-C was received.
+    Stop,
+}
+
+struct State {
+    clock: PlaybackClock,
+    seek: Option<u64>,
+    steps: usize,
+    position: u64,
+    lag: Duration,
+    iteration: u64,
+}
+
+/// Playback state shared between the replay loop and the control queryable.
+pub struct Playback {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Playback {
+    pub fn new(speed: f64, afap: bool) -> Self {
+        Self {
+            state: Mutex::new(State {
+                clock: PlaybackClock::new(speed, afap),
+                seek: None,
+                steps: 0,
+                position: 0,
+                lag: Duration::ZERO,
+                iteration: 0,
+            }),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        let state = self.lock();
+        PlaybackStatus {
+            paused: state.clock.is_paused(),
+            speed: state.clock.speed(),
+            afap: state.clock.is_afap(),
+            log_time: state.clock.log_now(),
+            position: state.position,
+            lag: state.lag,
+            iteration: state.iteration,
+        }
+    }
+
+    /// Header stamp for a message replayed with `--restamp`: the system time
+    /// at which `log_time` is scheduled on the playback clock.
+    pub fn restamp(&self, log_time: u64) -> Time {
+        let wall = self.lock().clock.wall_time(log_time);
+        Time::from_nanos(wall.as_nanos() as u64)
+    }
+
+    /// Count the start of a new pass over the recording and return its
+    /// iteration number.
+    pub fn start_loop(&self) -> u64 {
+        let mut state = self.lock();
+        state.iteration += 1;
+        state.iteration
+    }
+
+    /// Re-anchor the clock at `log_time`, e.g. at the start of each loop.
+    pub fn anchor(&self, log_time: u64) {
+        self.lock().clock.anchor(log_time);
+    }
+
+    pub fn pause(&self) -> PlaybackStatus {
+        self.lock().clock.pause();
+        self.wake.notify_all();
+        self.status()
+    }
+
+    pub fn resume(&self) -> PlaybackStatus {
+        {
+            let mut state = self.lock();
+            state.steps = 0;
+            state.clock.resume();
+        }
+        self.wake.notify_all();
+        self.status()
+    }
+
+    /// Pause (if playing) and release exactly one more message.
+    pub fn step(&self) -> PlaybackStatus {
+        {
+            let mut state = self.lock();
+            state.clock.pause();
+            state.steps += 1;
+        }
+        self.wake.notify_all();
+        self.status()
+    }
+
+    pub fn set_speed(&self, speed: f64) -> PlaybackStatus {
+        self.lock().clock.set_speed(speed);
+        self.wake.notify_all();
+        self.status()
+    }
+
+    /// Request a jump to `log_time`. The replay loop picks this up at its
+    /// next wait and restarts the message stream there.
+    pub fn seek(&self, log_time: u64) -> PlaybackStatus {
+        {
+            let mut state = self.lock();
+            state.seek = Some(log_time);
+            state.clock.anchor(log_time);
+        }
+        self.wake.notify_all();
+        self.status()
+    }
+
+    /// Block until `log_time` is due on the playback clock, honouring pause,
+    /// single-step and seek requests that arrive while waiting.
+    pub fn wait_until(&self, log_time: u64, run: &AtomicBool) -> Wake {
+        let mut state = self.lock();
+        loop {
+            if !run.load(Ordering::Relaxed) {
+                return Wake::Stop;
+            }
+            if let Some(t) = state.seek.take() {
+                return Wake::Seek(t);
+            }
+            let timeout = if state.clock.is_paused() {
+                if state.steps > 0 {
+                    state.steps -= 1;
+                    state.clock.anchor(log_time);
+                    state.position = log_time;
+                    state.lag = Duration::ZERO;
+                    return Wake::Publish;
+                }
+                WAIT_POLL
+            } else {
+                let remaining = state.clock.until(log_time);
+                if remaining.is_zero() {
+                    state.lag = state.clock.lag(log_time);
+                    state.position = log_time;
+                    if state.clock.is_afap() {
+                        state.clock.anchor(log_time);
+                    }
+                    return Wake::Publish;
+                }
+                remaining.min(WAIT_POLL)
+            };
+            state = self
+                .wake
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Playback, PlaybackClock, Wake};
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_clock_pause_and_speed() {
+        let mut clock = PlaybackClock::new(2.0, false);
+        clock.anchor(1_000_000_000);
+        clock.pause();
+        let paused_at = clock.log_now();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.log_now(), paused_at, "clock advanced while paused");
+
+        clock.resume();
+        clock.anchor(1_000_000_000);
+        // One second of log time at 2x is due in (at most) half a second.
+        let until = clock.until(2_000_000_000);
+        assert!(until <= Duration::from_millis(500), "got {until:?}");
+        assert!(until > Duration::from_millis(400), "got {until:?}");
+
+        clock.set_speed(0.5);
+        assert_eq!(clock.speed(), 0.5);
+        assert!(clock.until(2_000_000_000) > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn test_clock_afap() {
+        let mut clock = PlaybackClock::new(1.0, true);
+        clock.anchor(1_000_000_000);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.log_now(), 1_000_000_000);
+        assert!(clock.until(60_000_000_000).is_zero());
+        assert!(clock.lag(1_000_000_000).is_zero());
+    }
+
+    #[test]
+    fn test_wait_wakeups() {
+        let playback = Playback::new(1.0, false);
+        playback.anchor(0);
+        playback.pause();
+        let run = AtomicBool::new(true);
+
+        // A step releases a waiter blocked while paused, exactly once.
+        thread::scope(|s| {
+            let waiter = s.spawn(|| playback.wait_until(60_000_000_000, &run));
+            playback.step();
+            assert_eq!(waiter.join().unwrap(), Wake::Publish);
+        });
+        let status = playback.status();
+        assert!(status.paused);
+        assert_eq!(status.position, 60_000_000_000);
+
+        // A seek releases it too, with the target.
+        thread::scope(|s| {
+            let waiter = s.spawn(|| playback.wait_until(120_000_000_000, &run));
+            playback.seek(5_000_000_000);
+            assert_eq!(waiter.join().unwrap(), Wake::Seek(5_000_000_000));
+        });
+
+        // So does Ctrl-C, also while playing a message far in the future.
+        playback.resume();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| playback.wait_until(3_600_000_000_000, &run));
+            run.store(false, Ordering::Relaxed);
+            assert_eq!(waiter.join().unwrap(), Wake::Stop);
+        });
+    }
+}
@@ -0,0 +1,500 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Playlists of MCAP files replayed as one continuous timeline.
+//!
+//! Every positional argument may be a file, a directory (all `*.mcap` files
+//! inside it) or a glob pattern, which matters when the path comes from the
+//! `MCAP` environment variable and no shell expands it. The files are ordered
+//! by the log time of their first message and their message streams are
+//! k-way merged by log time, so segments of one drive play back to back and
+//! files recorded concurrently on different machines interleave.
+//!
+//! Each recording carries an offset that places its log times on the
+//! timeline: the `--time-offset` clock skew correction for that file plus,
+//! with `--collapse-gaps`, the time needed to pull it back to where the
+//! recordings before it ended. Everything downstream of
+//! [`Playlist::messages`] (pacing, seeking, status, restamping) works in
+//! timeline time, taken from the `--timing` source rather than the log time
+//! when one is chosen.
+
+use crate::{
+    args::TimeOffset,
+    source::{self, MessageIter, TimeRange},
+    timing::{self, Timing},
+};
+use log::{debug, info, warn};
+use mcap::{McapError, McapResult, Message, Summary};
+use memmap2::Mmap;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet, VecDeque},
+    fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    vec,
+};
+
+/// A single mapped MCAP file and its place on the timeline.
+pub struct Recording {
+    pub path: PathBuf,
+    pub mapped: Mmap,
+    pub summary: Option<Summary>,
+    /// Log time of the first message.
+    pub start: u64,
+    /// Log time of the last message, if the summary records it.
+    pub end: Option<u64>,
+    /// Nanoseconds added to the file's log times to place it on the timeline.
+    pub offset: i64,
+}
+
+impl Recording {
+    fn open(path: PathBuf) -> Result<Option<Self>, String> {
+        let mapped = map_mcap(&path)?;
+        let summary = match Summary::read(&mapped) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Could not read MCAP summary of {:?}: {:?}", path, e);
+                None
+            }
+        };
+        let Some(start) = source::first_log_time(&mapped, summary.as_ref()) else {
+            warn!("Skipping {:?}: no messages", path);
+            return Ok(None);
+        };
+        let end = source::last_log_time(summary.as_ref());
+        Ok(Some(Recording {
+            path,
+            mapped,
+            summary,
+            start,
+            end,
+            offset: 0,
+        }))
+    }
+
+    /// Map one of this file's log times onto the timeline.
+    pub fn to_timeline(&self, log_time: u64) -> u64 {
+        log_time.saturating_add_signed(self.offset)
+    }
+
+    /// Whether any of this file's messages fall within the timeline `range`.
+    fn overlaps(&self, range: TimeRange) -> bool {
+        let end = self.end.map_or(u64::MAX, |t| self.to_timeline(t));
+        end >= range.start && self.to_timeline(self.start) < range.end
+    }
+}
+
+/// The ordered set of recordings replayed as one timeline.
+pub struct Playlist {
+    pub recordings: Vec<Recording>,
+}
+
+impl Playlist {
+    /// Resolve, map and order the recordings given on the command line.
+    pub fn open(
+        paths: &[PathBuf],
+        offsets: &[TimeOffset],
+        collapse_gaps: bool,
+    ) -> Result<Self, String> {
+        let mut recordings = Vec::new();
+        for path in expand(paths)? {
+            if let Some(mut recording) = Recording::open(path)? {
+                if let Some(o) = offsets.iter().find(|o| o.matches(&recording.path)) {
+                    recording.offset = o.offset;
+                }
+                recordings.push(recording);
+            }
+        }
+        if recordings.is_empty() {
+            return Err("No MCAP recordings with messages to replay".to_owned());
+        }
+        for o in offsets {
+            if !recordings.iter().any(|r| o.matches(&r.path)) {
+                warn!("Time offset for {} matches no recording", o.pattern);
+            }
+        }
+        let timeline_start = |r: &Recording| r.to_timeline(r.start);
+        recordings.sort_by(|a, b| (timeline_start(a), &a.path).cmp(&(timeline_start(b), &b.path)));
+
+        if collapse_gaps {
+            let extents: Vec<_> = recordings
+                .iter()
+                .map(|r| (timeline_start(r), r.end.map(|t| r.to_timeline(t))))
+                .collect();
+            for (recording, offset) in recordings.iter_mut().zip(collapse_offsets(&extents)) {
+                recording.offset += offset;
+            }
+        }
+        for r in &recordings {
+            debug!(
+                "Playlist entry {:?}: log time {}..{:?}, offset {}",
+                r.path, r.start, r.end, r.offset
+            );
+        }
+        Ok(Playlist { recordings })
+    }
+
+    /// Timeline time of the first message.
+    pub fn start(&self) -> u64 {
+        let first = &self.recordings[0];
+        first.to_timeline(first.start)
+    }
+
+    /// Timeline time of the last message, if every file records its end.
+    pub fn end(&self) -> Option<u64> {
+        self.recordings
+            .iter()
+            .map(|r| r.end.map(|t| r.to_timeline(t)))
+            .try_fold(0, |end, t| t.map(|t| end.max(t)))
+    }
+
+    /// Timeline start of every recording, for reporting the file being
+    /// played.
+    pub fn file_starts(&self) -> Vec<(u64, String)> {
+        self.recordings
+            .iter()
+            .map(|r| (r.to_timeline(r.start), r.path.display().to_string()))
+            .collect()
+    }
+
+    /// Stream messages on `topics` within the timeline `range`, merged
+    /// across every recording in timeline order with their `timing` times
+    /// mapped onto the timeline.
+    pub fn messages<'a>(
+        &'a self,
+        topics: &'a HashSet<String>,
+        range: TimeRange,
+        timing: Timing,
+    ) -> MessageIter<'a> {
+        let pending: Vec<&Recording> = self
+            .recordings
+            .iter()
+            .filter(|r| r.overlaps(range))
+            .collect();
+        let open = move |r: &'a Recording| -> MessageIter<'a> {
+            info!("Replaying MCAP file {:?}", r.path);
+            let file_range = range.shift(-r.offset);
+            match source::messages(&r.mapped, r.summary.as_ref(), topics, file_range) {
+                Ok(v) => Box::new(timing::retime(v, timing).map(move |m| {
+                    m.map(|mut m| {
+                        m.log_time = r.to_timeline(m.log_time);
+                        m
+                    })
+                })),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        };
+        Box::new(Merge::new(pending, open))
+    }
+}
+
+/// K-way merge of the recordings' message streams by timeline log time.
+///
+/// A recording's stream is only opened once the merge reaches its first
+/// message, so a long playlist of segments keeps a single chunk in flight
+/// rather than one per file. Messages with equal log times are yielded in
+/// playlist order. Read errors are passed through as they are encountered.
+struct Merge<'a, F> {
+    /// Recordings not opened yet, in timeline order.
+    pending: Peekable<vec::IntoIter<&'a Recording>>,
+    open: F,
+    streams: Vec<MessageIter<'a>>,
+    /// Next message of each stream, once read.
+    heads: Vec<Option<Message<'a>>>,
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+    errors: VecDeque<McapError>,
+}
+
+impl<'a, F: FnMut(&'a Recording) -> MessageIter<'a>> Merge<'a, F> {
+    /// Merge the `pending` recordings, in timeline order, opening each
+    /// one's stream with `open`.
+    fn new(pending: Vec<&'a Recording>, open: F) -> Self {
+        Merge {
+            pending: pending.into_iter().peekable(),
+            open,
+            streams: Vec::new(),
+            heads: Vec::new(),
+            queue: BinaryHeap::new(),
+            errors: VecDeque::new(),
+        }
+    }
+
+    /// Read the next message of stream `index` into the queue.
+    fn advance(&mut self, index: usize) {
+        for message in self.streams[index].by_ref() {
+            match message {
+                Ok(m) => {
+                    self.queue.push(Reverse((m.log_time, index)));
+                    self.heads[index] = Some(m);
+                    return;
+                }
+                Err(e) => self.errors.push_back(e),
+            }
+        }
+    }
+}
+
+impl<'a, F: FnMut(&'a Recording) -> MessageIter<'a>> Iterator for Merge<'a, F> {
+    type Item = McapResult<Message<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.errors.pop_front() {
+                return Some(Err(e));
+            }
+            let next = self.queue.peek().map(|Reverse((t, _))| *t);
+            if let Some(r) = self
+                .pending
+                .next_if(|r| next.is_none_or(|t| r.to_timeline(r.start) <= t))
+            {
+                self.streams.push((self.open)(r));
+                self.heads.push(None);
+                self.advance(self.streams.len() - 1);
+                continue;
+            }
+            let Reverse((_, index)) = self.queue.pop()?;
+            let message = self.heads[index].take();
+            self.advance(index);
+            return message.map(Ok);
+        }
+    }
+}
+
+fn map_mcap<P: AsRef<Path>>(p: P) -> Result<Mmap, String> {
+    let fd = match fs::File::open(p.as_ref()) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Couldn't open MCAP file: {:#?} {e}", p.as_ref())),
+    };
+    match unsafe { Mmap::map(&fd) } {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("Couldn't map MCAP file: {e}")),
+    }
+}
+
+/// Expand directories and glob patterns into the list of MCAP files.
+fn expand(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let entries = fs::read_dir(path)
+                .map_err(|e| format!("Couldn't read directory {:?}: {e}", path))?;
+            let mut found: Vec<PathBuf> = entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "mcap"))
+                .collect();
+            if found.is_empty() {
+                return Err(format!("No MCAP files in directory {:?}", path));
+            }
+            found.sort();
+            files.extend(found);
+        } else if !path.exists() && is_pattern(path) {
+            let pattern = path.to_string_lossy();
+            let found: Vec<PathBuf> = glob::glob(&pattern)
+                .map_err(|e| format!("Invalid MCAP glob {pattern}: {e}"))?
+                .flatten()
+                .collect();
+            if found.is_empty() {
+                return Err(format!("No MCAP files match {pattern}"));
+            }
+            files.extend(found);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Offsets that close the gaps in the timeline, given the recordings'
+/// `(start, end)` log times in playback order. A recording is never pulled
+/// back over one that is still playing, so concurrent recordings keep their
+/// relative timing. A recording with an unknown end is taken to play until
+/// the next one starts: the gap after it is kept, and the gaps after that
+/// collapse against the latest end known so far.
+fn collapse_offsets(extents: &[(u64, Option<u64>)]) -> Vec<i64> {
+    let mut offsets = Vec::with_capacity(extents.len());
+    let mut offset = 0i64;
+    // Latest end among the recordings so far.
+    let mut until: Option<u64> = None;
+    // Whether the previous recording's end is unknown.
+    let mut open = false;
+    for &(start, end) in extents {
+        if let Some(until) = until.filter(|_| !open) {
+            if start > until {
+                offset -= (start - until) as i64;
+            }
+        }
+        offsets.push(offset);
+        let reached = end.unwrap_or(start);
+        until = Some(until.map_or(reached, |until| until.max(reached)));
+        open = end.is_none();
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collapse_offsets, Merge, Playlist};
+    use crate::{
+        args::parse_time_offset,
+        source::{self, tests::recording, MessageIter, TimeRange},
+        timing::Timing,
+    };
+    use std::{cell::RefCell, collections::HashSet, fs, path::PathBuf};
+
+    const MS: u64 = 1_000_000;
+
+    /// Write single-chunk recordings of `(topic, log_time in ms)` messages
+    /// into a fresh directory named after `test`.
+    fn recordings(test: &str, files: &[(&str, &[(&str, u64)])]) -> Vec<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("replay-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .map(|(name, messages)| {
+                let messages: Vec<_> = messages.iter().map(|&(t, ms)| (t, ms * MS)).collect();
+                let path = dir.join(name);
+                fs::write(&path, recording(&[&messages], true)).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn topics(playlist: &Playlist) -> HashSet<String> {
+        playlist
+            .recordings
+            .iter()
+            .flat_map(|r| r.summary.as_ref().unwrap().channels.values())
+            .map(|c| c.topic.clone())
+            .collect()
+    }
+
+    /// The `(topic, timeline time in ms)` of every message in `stream`.
+    fn timeline(stream: MessageIter) -> Vec<(String, u64)> {
+        stream
+            .map(|m| {
+                let m = m.unwrap();
+                (m.channel.topic.clone(), m.log_time / MS)
+            })
+            .collect()
+    }
+
+    fn expected(messages: &[(&str, u64)]) -> Vec<(String, u64)> {
+        messages.iter().map(|&(t, ms)| (t.to_owned(), ms)).collect()
+    }
+
+    #[test]
+    fn test_merge_interleaves() {
+        let paths = recordings(
+            "interleave",
+            &[
+                ("b.mcap", &[("/b", 10), ("/b", 20), ("/b", 30)]),
+                ("a.mcap", &[("/a", 0), ("/a", 20), ("/a", 40)]),
+            ],
+        );
+        let playlist = Playlist::open(&paths, &[], false).unwrap();
+        let topics = topics(&playlist);
+        let merged = timeline(playlist.messages(&topics, TimeRange::ALL, Timing::Log));
+        // a.mcap starts first, so its message wins the tie at 20 ms.
+        assert_eq!(
+            merged,
+            expected(&[
+                ("/a", 0),
+                ("/b", 10),
+                ("/a", 20),
+                ("/b", 20),
+                ("/b", 30),
+                ("/a", 40),
+            ])
+        );
+
+        // Shifting b.mcap by 15 ms moves its messages on the timeline.
+        let offsets = [parse_time_offset("b.mcap=15ms").unwrap()];
+        let playlist = Playlist::open(&paths, &offsets, false).unwrap();
+        let merged = timeline(playlist.messages(&topics, TimeRange::ALL, Timing::Log));
+        assert_eq!(
+            merged,
+            expected(&[
+                ("/a", 0),
+                ("/a", 20),
+                ("/b", 25),
+                ("/b", 35),
+                ("/a", 40),
+                ("/b", 45),
+            ])
+        );
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_merge_opens_lazily() {
+        let paths = recordings(
+            "lazy",
+            &[
+                ("a.mcap", &[("/a", 0), ("/a", 20)]),
+                ("b.mcap", &[("/b", 10)]),
+                ("c.mcap", &[("/c", 1000)]),
+            ],
+        );
+        let playlist = Playlist::open(&paths, &[], false).unwrap();
+        let topics = topics(&playlist);
+        let opened = RefCell::new(Vec::new());
+        let mut merge = Merge::new(playlist.recordings.iter().collect(), |r| {
+            let name = r.path.file_name().unwrap().to_string_lossy().into_owned();
+            opened.borrow_mut().push(name);
+            source::messages(&r.mapped, r.summary.as_ref(), &topics, TimeRange::ALL).unwrap()
+        });
+        let mut next = || {
+            let m = merge.next().unwrap().unwrap();
+            (m.channel.topic.clone(), m.log_time / MS)
+        };
+
+        // Each file is opened once the merge reaches its first message.
+        assert_eq!(next(), ("/a".to_owned(), 0));
+        assert_eq!(*opened.borrow(), ["a.mcap"]);
+        assert_eq!(next(), ("/b".to_owned(), 10));
+        assert_eq!(next(), ("/a".to_owned(), 20));
+        assert_eq!(*opened.borrow(), ["a.mcap", "b.mcap"]);
+        assert_eq!(next(), ("/c".to_owned(), 1000));
+        assert_eq!(*opened.borrow(), ["a.mcap", "b.mcap", "c.mcap"]);
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_collapse_offsets() {
+        let extents = [
+            (100, Some(200)),
+            (500, Some(700)),
+            (650, Some(900)),
+            (1000, None),
+            (5000, Some(6000)),
+        ];
+        assert_eq!(collapse_offsets(&extents), [0, -300, -300, -400, -400]);
+
+        // Concurrent recordings keep their relative timing.
+        let extents = [
+            (0, Some(1000)),
+            (10, Some(20)),
+            (500, Some(600)),
+            (1100, Some(1200)),
+        ];
+        assert_eq!(collapse_offsets(&extents), [0, 0, 0, -100]);
+
+        // The gap after a recording with an unknown end is kept, and later
+        // gaps still collapse against the ends known before it.
+        let extents = [
+            (0, Some(1000)),
+            (10, None),
+            (500, Some(600)),
+            (1100, Some(1200)),
+            (1500, Some(1600)),
+        ];
+        assert_eq!(collapse_offsets(&extents), [0, 0, 0, -100, -400]);
+    }
+}
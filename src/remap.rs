@@ -0,0 +1,305 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Topic prefix policy, output topic remapping and namespacing.
+//!
+//! Recorded MCAP topics (`/camera/h264`) become Zenoh keys by adding the
+//! `--topic-prefix` (`rt` by default, or none). Topics that already carry
+//! the prefix, e.g. from a bridged ROS 2 system, are not prefixed twice.
+//!
+//! `--remap from=to` rules are Zenoh key expressions. Wildcard chunks in
+//! `from` (`*` for one chunk, `**` for any number) capture the key chunks
+//! they match, and the wildcards in `to` are filled with those captures in
+//! order, so `rt/camera/**=rt/front/**` moves a whole subtree. A `to`
+//! without wildcards maps every match onto a single key. The first matching
+//! rule wins. Either side may use the `/camera/**` shorthand for the
+//! prefixed key.
+//!
+//! `--namespace` is inserted after the topic prefix of the remapped key (or
+//! prepended when there is none), so `--namespace replayA` publishes
+//! `rt/camera/h264` as `rt/replayA/camera/h264`.
+
+use std::str::FromStr;
+use zenoh::key_expr::OwnedKeyExpr;
+
+/// Prefix policy mapping recorded topics onto Zenoh keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPrefix(Option<String>);
+
+impl Default for TopicPrefix {
+    fn default() -> Self {
+        TopicPrefix(Some("rt".to_owned()))
+    }
+}
+
+impl FromStr for TopicPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefix = s.trim_matches('/');
+        if prefix.is_empty() || prefix == "none" {
+            return Ok(TopicPrefix(None));
+        }
+        match OwnedKeyExpr::autocanonize(prefix.to_owned()) {
+            Ok(k) if !k.is_wild() => Ok(TopicPrefix(Some(k.to_string()))),
+            _ => Err(format!("'{s}' is not a valid topic prefix")),
+        }
+    }
+}
+
+impl TopicPrefix {
+    /// Whether `topic` (without a leading `/`) already starts with the
+    /// prefix.
+    fn is_prefixed(&self, topic: &str) -> bool {
+        match &self.0 {
+            None => true,
+            Some(p) => topic
+                .strip_prefix(p.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+        }
+    }
+
+    /// Zenoh key for a recorded MCAP topic.
+    pub fn key(&self, topic: &str) -> String {
+        let topic = topic.trim_start_matches('/');
+        match &self.0 {
+            Some(p) if !self.is_prefixed(topic) => format!("{p}/{topic}"),
+            _ => topic.to_owned(),
+        }
+    }
+
+    /// Expand a user-supplied topic: `/camera/**` is shorthand for the
+    /// prefixed key, anything else is taken as a full key.
+    pub fn expand(&self, topic: &str) -> String {
+        if topic.starts_with('/') {
+            self.key(topic)
+        } else {
+            topic.to_owned()
+        }
+    }
+
+    /// Topic with the prefix (and any leading `/`) removed.
+    pub fn strip<'a>(&self, topic: &'a str) -> &'a str {
+        let topic = topic.trim_start_matches('/');
+        match &self.0 {
+            Some(p) if self.is_prefixed(topic) => topic[p.len()..].trim_start_matches('/'),
+            _ => topic,
+        }
+    }
+}
+
+/// A single `from=to` remap rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemapRule {
+    from: Vec<String>,
+    to: Vec<String>,
+    /// Whether `from` / `to` were given with the `/` shorthand and still need
+    /// the topic prefix.
+    relative: (bool, bool),
+}
+
+fn is_wild(chunk: &str) -> bool {
+    chunk == "*" || chunk == "**"
+}
+
+fn parse_key(key: &str) -> Result<(Vec<String>, bool), String> {
+    let relative = key.starts_with('/');
+    let key = OwnedKeyExpr::autocanonize(key.trim_start_matches('/').to_owned())
+        .map_err(|_| format!("Could not parse key expression: {key}"))?;
+    Ok((key.split('/').map(str::to_owned).collect(), relative))
+}
+
+impl FromStr for RemapRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("remap rule '{s}' must have the form from=to"))?;
+        let (from, from_relative) = parse_key(from)?;
+        let (to, to_relative) = parse_key(to)?;
+        let from_wild = from.iter().filter(|c| is_wild(c)).count();
+        let to_wild = to.iter().filter(|c| is_wild(c)).count();
+        if to_wild != 0 && to_wild != from_wild {
+            return Err(format!(
+                "remap rule '{s}': target has {to_wild} wildcards but source has {from_wild}"
+            ));
+        }
+        Ok(RemapRule {
+            from,
+            to,
+            relative: (from_relative, to_relative),
+        })
+    }
+}
+
+impl RemapRule {
+    /// Resolve `/` shorthand on either side against `prefix`.
+    fn with_prefix(mut self, prefix: &TopicPrefix) -> Self {
+        if let Some(p) = &prefix.0 {
+            let chunks = p.split('/').map(str::to_owned);
+            if self.relative.0 {
+                self.from.splice(0..0, chunks.clone());
+            }
+            if self.relative.1 {
+                self.to.splice(0..0, chunks);
+            }
+        }
+        self.relative = (false, false);
+        self
+    }
+
+    /// Apply the rule to `key`, returning the remapped key if it matches.
+    fn apply(&self, key: &[&str]) -> Option<String> {
+        let from: Vec<&str> = self.from.iter().map(String::as_str).collect();
+        let mut captures = Vec::new();
+        if !capture(&from, key, &mut captures) {
+            return None;
+        }
+        let mut captures = captures.into_iter();
+        let mut out: Vec<String> = Vec::with_capacity(self.to.len());
+        for chunk in &self.to {
+            if is_wild(chunk) {
+                let captured = captures.next().unwrap_or_default();
+                if !captured.is_empty() {
+                    out.push(captured.join("/"));
+                }
+            } else {
+                out.push(chunk.clone());
+            }
+        }
+        Some(out.join("/"))
+    }
+}
+
+/// Match `key` against `pattern`, recording the chunks each wildcard
+/// consumed.
+fn capture<'k>(pattern: &[&str], key: &[&'k str], out: &mut Vec<Vec<&'k str>>) -> bool {
+    let Some((&head, rest)) = pattern.split_first() else {
+        return key.is_empty();
+    };
+    if head == "**" {
+        for n in 0..=key.len() {
+            out.push(key[..n].to_vec());
+            if capture(rest, &key[n..], out) {
+                return true;
+            }
+            out.pop();
+        }
+        return false;
+    }
+    let Some((&chunk, key_rest)) = key.split_first() else {
+        return false;
+    };
+    if head == "*" {
+        out.push(vec![chunk]);
+        if capture(rest, key_rest, out) {
+            return true;
+        }
+        out.pop();
+        false
+    } else {
+        head == chunk && capture(rest, key_rest, out)
+    }
+}
+
+/// Resolves the key every replayed or synthesised message is published on.
+#[derive(Debug, Clone, Default)]
+pub struct TopicMapper {
+    prefix: TopicPrefix,
+    rules: Vec<RemapRule>,
+    namespace: String,
+}
+
+impl TopicMapper {
+    pub fn new(prefix: TopicPrefix, rules: Vec<RemapRule>, namespace: &str) -> Self {
+        let rules = rules.into_iter().map(|r| r.with_prefix(&prefix)).collect();
+        Self {
+            prefix,
+            rules,
+            namespace: namespace.trim_matches('/').to_owned(),
+        }
+    }
+
+    /// Map an output key through the remap rules and namespace.
+    ///
+    /// Fails when the result isn't a concrete key, e.g. a rule that maps
+    /// onto a wildcard target.
+    pub fn map(&self, key: &str) -> Result<String, String> {
+        let chunks: Vec<&str> = key.split('/').collect();
+        let mut mapped = self
+            .rules
+            .iter()
+            .find_map(|r| r.apply(&chunks))
+            .unwrap_or_else(|| key.to_owned());
+        if !self.namespace.is_empty() {
+            mapped = match &self.prefix.0 {
+                Some(p) if self.prefix.is_prefixed(&mapped) => {
+                    let rest = self.prefix.strip(&mapped);
+                    format!("{p}/{}/{rest}", self.namespace)
+                }
+                _ => format!("{}/{mapped}", self.namespace),
+            };
+        }
+        match OwnedKeyExpr::autocanonize(mapped.clone()) {
+            Ok(k) if !k.is_wild() => Ok(k.to_string()),
+            _ => Err(format!("'{key}' maps to invalid output key '{mapped}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemapRule, TopicMapper, TopicPrefix};
+
+    #[test]
+    fn test_remap() {
+        let rules: Vec<RemapRule> = [
+            "rt/camera/**=rt/front/camera/**",
+            "rt/*/info=rt/info/*",
+            "/radar/cube=rt/radar/cube_raw",
+        ]
+        .iter()
+        .map(|r| r.parse().unwrap())
+        .collect();
+        let mapper = TopicMapper::new(TopicPrefix::default(), rules, "");
+        assert_eq!(
+            mapper.map("rt/camera/h264").unwrap(),
+            "rt/front/camera/h264"
+        );
+        assert_eq!(mapper.map("rt/camera").unwrap(), "rt/front/camera");
+        assert_eq!(mapper.map("rt/radar/info").unwrap(), "rt/info/radar");
+        assert_eq!(mapper.map("rt/radar/cube").unwrap(), "rt/radar/cube_raw");
+        assert_eq!(mapper.map("rt/imu").unwrap(), "rt/imu");
+
+        let mapper = TopicMapper::new(TopicPrefix::default(), Vec::new(), "/replayA/");
+        assert_eq!(
+            mapper.map("rt/camera/dma").unwrap(),
+            "rt/replayA/camera/dma"
+        );
+        let mapper = TopicMapper::new("none".parse().unwrap(), Vec::new(), "replayA");
+        assert_eq!(mapper.map("camera/dma").unwrap(), "replayA/camera/dma");
+
+        assert!("rt/*/info=rt/**/x/*".parse::<RemapRule>().is_err());
+        assert!("rt/camera".parse::<RemapRule>().is_err());
+    }
+
+    #[test]
+    fn test_topic_prefix() {
+        let rt = TopicPrefix::default();
+        assert_eq!(rt.key("/camera/h264"), "rt/camera/h264");
+        assert_eq!(rt.key("/rt/camera/h264"), "rt/camera/h264");
+        assert_eq!(rt.key("/rtk/fix"), "rt/rtk/fix");
+        assert_eq!(rt.expand("/camera/**"), "rt/camera/**");
+        assert_eq!(rt.expand("other/camera"), "other/camera");
+        assert_eq!(rt.strip("/rt/camera/h264"), "camera/h264");
+        assert_eq!(rt.strip("/camera/h264"), "camera/h264");
+
+        let none: TopicPrefix = "none".parse().unwrap();
+        assert_eq!(none.key("/camera/h264"), "camera/h264");
+
+        let custom: TopicPrefix = "/robot/rt/".parse().unwrap();
+        assert_eq!(custom.key("/camera/h264"), "robot/rt/camera/h264");
+        assert_eq!(custom.strip("robot/rt/camera/h264"), "camera/h264");
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Header stamp rewriting for `--restamp`, and reading for `--timing header`.
+//!
+//! Every schema listed here starts with a `std_msgs/Header` (or, for
+//! Foxglove `CompressedVideo`, a `builtin_interfaces/Time` timestamp), so
+//! the stamp sits directly after the 4-byte CDR encapsulation header and can
+//! be read or rewritten in place without decoding the message.
+
+use edgefirst_schemas::builtin_interfaces::Time;
+use std::error::Error;
+
+/// Size of the CDR encapsulation header preceding the message body.
+const CDR_HEADER_SIZE: usize = 4;
+
+/// Schemas known to `edgefirst-schemas` whose first field is a header stamp.
+const STAMPED_SCHEMAS: &[&str] = &[
+    "edgefirst_msgs/msg/CameraFrame",
+    "edgefirst_msgs/msg/Detect",
+    "edgefirst_msgs/msg/DmaBuffer",
+    "edgefirst_msgs/msg/LocalTime",
+    "edgefirst_msgs/msg/Model",
+    "edgefirst_msgs/msg/ModelInfo",
+    "edgefirst_msgs/msg/RadarCube",
+    "edgefirst_msgs/msg/RadarInfo",
+    "edgefirst_msgs/msg/Vibration",
+    "foxglove_msgs/msg/CompressedVideo",
+    "geometry_msgs/msg/AccelStamped",
+    "geometry_msgs/msg/AccelWithCovarianceStamped",
+    "geometry_msgs/msg/InertiaStamped",
+    "geometry_msgs/msg/PointStamped",
+    "geometry_msgs/msg/PolygonStamped",
+    "geometry_msgs/msg/PoseArray",
+    "geometry_msgs/msg/PoseStamped",
+    "geometry_msgs/msg/PoseWithCovarianceStamped",
+    "geometry_msgs/msg/QuaternionStamped",
+    "geometry_msgs/msg/TransformStamped",
+    "geometry_msgs/msg/TwistStamped",
+    "geometry_msgs/msg/TwistWithCovarianceStamped",
+    "geometry_msgs/msg/Vector3Stamped",
+    "geometry_msgs/msg/WrenchStamped",
+    "nav_msgs/msg/Odometry",
+    "sensor_msgs/msg/BatteryState",
+    "sensor_msgs/msg/CameraInfo",
+    "sensor_msgs/msg/CompressedImage",
+    "sensor_msgs/msg/FluidPressure",
+    "sensor_msgs/msg/Image",
+    "sensor_msgs/msg/Imu",
+    "sensor_msgs/msg/MagneticField",
+    "sensor_msgs/msg/NavSatFix",
+    "sensor_msgs/msg/PointCloud2",
+    "sensor_msgs/msg/Temperature",
+    "std_msgs/msg/Header",
+];
+
+/// Whether messages of `schema` carry a header stamp we know how to find.
+pub fn has_stamp(schema: &str) -> bool {
+    STAMPED_SCHEMAS.contains(&schema)
+}
+
+/// Read the header stamp of a CDR-encoded message.
+pub fn stamp(data: &[u8]) -> Result<Time, Box<dyn Error>> {
+    if data.len() < CDR_HEADER_SIZE + 8 {
+        return Err(format!("CDR message too short for a header ({} bytes)", data.len()).into());
+    }
+    let sec: [u8; 4] = data[CDR_HEADER_SIZE..CDR_HEADER_SIZE + 4].try_into()?;
+    let nanosec: [u8; 4] = data[CDR_HEADER_SIZE + 4..CDR_HEADER_SIZE + 8].try_into()?;
+    match data[1] {
+        0 => Ok(Time::new(
+            i32::from_be_bytes(sec),
+            u32::from_be_bytes(nanosec),
+        )),
+        1 => Ok(Time::new(
+            i32::from_le_bytes(sec),
+            u32::from_le_bytes(nanosec),
+        )),
+        other => Err(format!("unsupported CDR encapsulation kind {other}").into()),
+    }
+}
+
+/// Overwrite the header stamp of a CDR-encoded message in place.
+///
+/// Honours the byte order declared by the encapsulation header, so both
+/// `CDR_LE` and `CDR_BE` payloads are handled.
+pub fn set_stamp(data: &mut [u8], stamp: Time) -> Result<(), Box<dyn Error>> {
+    if data.len() < CDR_HEADER_SIZE + 8 {
+        return Err(format!("CDR message too short for a header ({} bytes)", data.len()).into());
+    }
+    let (sec, nanosec) = match data[1] {
+        0 => (stamp.sec.to_be_bytes(), stamp.nanosec.to_be_bytes()),
+        1 => (stamp.sec.to_le_bytes(), stamp.nanosec.to_le_bytes()),
+        other => return Err(format!("unsupported CDR encapsulation kind {other}").into()),
+    };
+    data[CDR_HEADER_SIZE..CDR_HEADER_SIZE + 4].copy_from_slice(&sec);
+    data[CDR_HEADER_SIZE + 4..CDR_HEADER_SIZE + 8].copy_from_slice(&nanosec);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_stamp, stamp};
+    use edgefirst_schemas::{builtin_interfaces::Time, std_msgs::Header};
+
+    #[test]
+    fn test_set_stamp() {
+        let mut data = Header::builder()
+            .stamp(Time::new(1, 2))
+            .frame_id("camera")
+            .build()
+            .unwrap()
+            .into_cdr();
+        assert_eq!(stamp(&data).unwrap(), Time::new(1, 2));
+        set_stamp(&mut data, Time::new(1_760_000_000, 123_456_789)).unwrap();
+        assert_eq!(stamp(&data).unwrap(), Time::new(1_760_000_000, 123_456_789));
+        let header = Header::from_cdr(data.as_slice()).unwrap();
+        assert_eq!(header.stamp(), Time::new(1_760_000_000, 123_456_789));
+        assert_eq!(header.frame_id(), "camera");
+    }
+}
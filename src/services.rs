@@ -3,16 +3,18 @@
 
 //! System service management for topic conflict resolution.
 
+use crate::remap::TopicPrefix;
 use log::{debug, info, warn};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::process::Command;
 pub struct ServiceHandler {
     service_map: HashMap<String, String>,
+    prefix: TopicPrefix,
 }
 const NO_ASSOCIATED_SERVICE: &str = "NONE";
 impl ServiceHandler {
-    pub fn new() -> Self {
+    pub fn new(prefix: TopicPrefix) -> Self {
         let lookup: HashMap<String, Value> =
             serde_json::from_str(include_str!("services.json")).unwrap();
         let mut map = HashMap::new();
@@ -21,7 +23,10 @@ impl ServiceHandler {
                 map.insert(key.to_owned(), v.to_owned());
             }
         }
-        ServiceHandler { service_map: map }
+        ServiceHandler {
+            service_map: map,
+            prefix,
+        }
     }
 
     pub fn stop_services<'a, I>(&self, topics: I)
@@ -43,13 +48,7 @@ impl ServiceHandler {
     }
 
     fn topic_to_service(&self, topic: &str) -> String {
-        let topic = if let Some(t) = topic.strip_prefix("rt/") {
-            t
-        } else if let Some(t) = topic.strip_prefix("/") {
-            t
-        } else {
-            topic
-        };
+        let topic = self.prefix.strip(topic);
 
         let topic = topic.split("/").next().unwrap();
         if self.service_map.contains_key(topic) {
@@ -81,10 +80,11 @@ impl ServiceHandler {
 #[cfg(test)]
 mod tests {
     use super::ServiceHandler;
+    use crate::remap::TopicPrefix;
 
     #[test]
     fn test_service_to_topic() {
-        let s = ServiceHandler::new();
+        let s = ServiceHandler::new(TopicPrefix::default());
         let service = s.topic_to_service("/camera/h264");
         assert_eq!(
             service, "camera",
@@ -92,4 +92,11 @@ mod tests {
             service
         )
     }
+
+    #[test]
+    fn test_service_to_prefixed_topic() {
+        let s = ServiceHandler::new(TopicPrefix::default());
+        let service = s.topic_to_service("/rt/radar/cube");
+        assert_eq!(service, "radarpub");
+    }
 }
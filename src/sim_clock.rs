@@ -0,0 +1,123 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Simulated clock publication for sim-time aware consumers.
+//!
+//! Publishes `rosgraph_msgs/Clock` at a fixed wall-clock rate, sampled from
+//! the same playback clock that paces the replay loop. The published time
+//! stops while paused, jumps on seek and restarts with each loop.
+
+use crate::playback::{Playback, PlaybackStatus};
+use edgefirst_schemas::{builtin_interfaces::Time, cdr::encode_fixed, rosgraph_msgs::Clock};
+use log::{info, warn};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+use zenoh::{
+    bytes::{Encoding, ZBytes},
+    Session, Wait,
+};
+
+const CLOCK_SCHEMA: &str = "rosgraph_msgs/msg/Clock";
+
+/// Simulated time for a playback `status`: the playback clock's position,
+/// or `None` before replay starts.
+fn clock(status: &PlaybackStatus) -> Option<Clock> {
+    (status.iteration > 0).then(|| Clock {
+        clock: Time::from_nanos(status.log_time),
+    })
+}
+
+/// Spawn the clock publisher thread. Runs until `run` is cleared.
+pub fn spawn(
+    session: Session,
+    topic: String,
+    rate: f64,
+    playback: Arc<Playback>,
+    run: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    info!("Publishing simulated clock on {topic} at {rate} Hz");
+    let period = Duration::from_secs_f64(1.0 / rate);
+    thread::spawn(move || {
+        let enc = Encoding::APPLICATION_CDR.with_schema(CLOCK_SCHEMA);
+        // Ticks are scheduled against a fixed start so the publish rate
+        // doesn't drift with the time spent publishing.
+        let mut next = Instant::now();
+        while run.load(Ordering::Relaxed) {
+            next += period;
+            sleep(next.saturating_duration_since(Instant::now()));
+
+            let Some(msg) = clock(&playback.status()) else {
+                continue;
+            };
+            let bytes = match encode_fixed(&msg) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Could not encode clock message: {:?}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = session
+                .put(&topic, ZBytes::from(bytes))
+                .encoding(enc.clone())
+                .wait()
+            {
+                warn!("Error sending clock message on {topic}: {:?}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clock;
+    use crate::playback::Playback;
+    use std::{
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_clock_follows_playback() {
+        let start = 5_000_000_000;
+        let playback = Playback::new(4.0, false);
+        let sample = || clock(&playback.status()).map(|c| c.clock.to_nanos().unwrap());
+        assert_eq!(sample(), None, "clock before replay starts");
+
+        playback.start_loop();
+        playback.anchor(start);
+        playback.pause();
+        let paused = sample().unwrap();
+        sleep(Duration::from_millis(5));
+        assert_eq!(sample(), Some(paused), "clock advanced while paused");
+
+        // A seek while paused lands exactly on the target.
+        playback.seek(start);
+        assert_eq!(sample(), Some(start));
+
+        // At 4x the clock advances four times the wall time between the
+        // resume and the sample. Both are bracketed by instants taken around
+        // them, so the bounds hold however long the runner stalls.
+        let before_resume = Instant::now();
+        playback.resume();
+        let after_resume = Instant::now();
+        sleep(Duration::from_millis(10));
+        let before_sample = Instant::now();
+        let elapsed = sample().unwrap() - start;
+        let after_sample = Instant::now();
+        let at_4x = |wall: Duration| wall.as_nanos() as u64 * 4;
+        assert!(
+            elapsed + 1_000 >= at_4x(before_sample - after_resume),
+            "clock advanced {elapsed} ns"
+        );
+        assert!(
+            elapsed <= at_4x(after_sample - before_resume) + 1_000,
+            "clock advanced {elapsed} ns"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! MCAP message iteration over a log-time range.
+//!
+//! When the recording carries a summary with chunk indexes, replay jumps
+//! straight to the chunks overlapping the requested range and uses each
+//! chunk's message indexes to skip chunks with nothing to publish. Files
+//! without chunk indexes (e.g. truncated recordings) fall back to a linear
+//! scan from the first byte that stops at the end of the range.
+
+use log::{debug, info};
+use mcap::{records::ChunkIndex, McapResult, Message, Summary};
+use std::collections::HashSet;
+
+pub type MessageIter<'a> = Box<dyn Iterator<Item = McapResult<Message<'a>>> + 'a>;
+
+/// Log-time range `[start, end)` in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TimeRange {
+    pub const ALL: TimeRange = TimeRange {
+        start: 0,
+        end: u64::MAX,
+    };
+
+    pub fn contains(&self, log_time: u64) -> bool {
+        self.start <= log_time && log_time < self.end
+    }
+
+    /// The same range moved by `offset` nanoseconds. The open `end` of
+    /// [`TimeRange::ALL`] stays open.
+    pub fn shift(&self, offset: i64) -> TimeRange {
+        TimeRange {
+            start: self.start.saturating_add_signed(offset),
+            end: match self.end {
+                u64::MAX => u64::MAX,
+                end => end.saturating_add_signed(offset),
+            },
+        }
+    }
+
+    fn overlaps(&self, chunk: &ChunkIndex) -> bool {
+        chunk.message_end_time >= self.start && chunk.message_start_time < self.end
+    }
+}
+
+/// Log time of the first message in the recording.
+///
+/// Prefers the summary statistics, then the chunk indexes, and only scans
+/// the data section when neither is available.
+pub fn first_log_time(mapped: &[u8], summary: Option<&Summary>) -> Option<u64> {
+    if let Some(summary) = summary {
+        if let Some(stats) = &summary.stats {
+            if stats.message_count > 0 {
+                return Some(stats.message_start_time);
+            }
+        }
+        if let Some(t) = summary
+            .chunk_indexes
+            .iter()
+            .map(|c| c.message_start_time)
+            .min()
+        {
+            return Some(t);
+        }
+    }
+    mcap::MessageStream::new(mapped)
+        .ok()?
+        .flatten()
+        .map(|m| m.log_time)
+        .next()
+}
+
+/// Log time of the last message in the recording, if the summary records it.
+pub fn last_log_time(summary: Option<&Summary>) -> Option<u64> {
+    let summary = summary?;
+    if let Some(stats) = &summary.stats {
+        if stats.message_count > 0 {
+            return Some(stats.message_end_time);
+        }
+    }
+    summary
+        .chunk_indexes
+        .iter()
+        .map(|c| c.message_end_time)
+        .max()
+}
+
+/// Stream messages on `topics` whose log time falls within `range`.
+///
+/// Messages are yielded in log-time order: chunks are read by start time,
+/// and chunks whose time ranges overlap are read together and sorted. The
+/// linear fallback yields file order, which for recorder output is log-time
+/// order, and stops at the first message past the end of the range.
+pub fn messages<'a>(
+    mapped: &'a [u8],
+    summary: Option<&'a Summary>,
+    topics: &'a HashSet<String>,
+    range: TimeRange,
+) -> McapResult<MessageIter<'a>> {
+    let summary = match summary {
+        Some(s) if !s.chunk_indexes.is_empty() => s,
+        _ => {
+            debug!("No chunk indexes in MCAP summary, scanning linearly");
+            let stream = mcap::MessageStream::new(mapped)?
+                .take_while(move |m| m.as_ref().map_or(true, |m| m.log_time < range.end))
+                .filter_map(move |m| select(m, topics, range));
+            return Ok(Box::new(stream));
+        }
+    };
+
+    let channel_ids: HashSet<u16> = summary
+        .channels
+        .values()
+        .filter(|c| topics.contains(&c.topic))
+        .map(|c| c.id)
+        .collect();
+
+    let mut chunks: Vec<&'a ChunkIndex> = summary
+        .chunk_indexes
+        .iter()
+        .filter(|c| range.overlaps(c))
+        .filter(|c| {
+            c.message_index_offsets.is_empty()
+                || c.message_index_offsets
+                    .keys()
+                    .any(|id| channel_ids.contains(id))
+        })
+        .filter(|c| chunk_has_messages_in_range(mapped, summary, c, &channel_ids, range))
+        .collect();
+    chunks.sort_by_key(|c| (c.message_start_time, c.chunk_start_offset));
+
+    if range != TimeRange::ALL {
+        info!(
+            "Seeking via chunk index: {} of {} chunks overlap the replay range",
+            chunks.len(),
+            summary.chunk_indexes.len()
+        );
+    }
+
+    // Runs of chunks with overlapping time ranges.
+    let mut groups: Vec<Vec<&'a ChunkIndex>> = Vec::new();
+    let mut group_end = 0;
+    for chunk in chunks {
+        match groups.last_mut() {
+            Some(group) if chunk.message_start_time < group_end => {
+                group.push(chunk);
+                group_end = group_end.max(chunk.message_end_time);
+            }
+            _ => {
+                groups.push(vec![chunk]);
+                group_end = chunk.message_end_time;
+            }
+        }
+    }
+
+    let stream = groups
+        .into_iter()
+        .flat_map(move |group| -> MessageIter<'a> {
+            if let [chunk] = group.as_slice() {
+                let stream = stream_chunk(mapped, summary, chunk);
+                return Box::new(stream.filter_map(move |m| select(m, topics, range)));
+            }
+            debug!(
+                "Merging {} chunks with overlapping time ranges",
+                group.len()
+            );
+            let mut merged: Vec<_> = group
+                .into_iter()
+                .flat_map(|chunk| stream_chunk(mapped, summary, chunk))
+                .filter_map(|m| select(m, topics, range))
+                .collect();
+            // Stable, so messages with the same log time keep file order.
+            merged.sort_by_key(|m| m.as_ref().map_or(0, |m| m.log_time));
+            Box::new(merged.into_iter())
+        });
+    Ok(Box::new(stream))
+}
+
+fn stream_chunk<'a>(
+    mapped: &'a [u8],
+    summary: &'a Summary,
+    chunk: &'a ChunkIndex,
+) -> MessageIter<'a> {
+    match summary.stream_chunk(mapped, chunk) {
+        Ok(v) => Box::new(v),
+        Err(e) => Box::new(std::iter::once(Err(e))),
+    }
+}
+
+/// Keep messages on `topics` within `range`; errors are passed through so
+/// the replay loop can report them.
+fn select<'a>(
+    message: McapResult<Message<'a>>,
+    topics: &HashSet<String>,
+    range: TimeRange,
+) -> Option<McapResult<Message<'a>>> {
+    match message {
+        Ok(m) if !range.contains(m.log_time) || !topics.contains(&m.channel.topic) => None,
+        other => Some(other),
+    }
+}
+
+/// Consult the chunk's message indexes for chunks straddling a range edge.
+///
+/// Chunks entirely inside the range always qualify. Chunks without message
+/// indexes are kept and filtered message by message instead.
+fn chunk_has_messages_in_range(
+    mapped: &[u8],
+    summary: &Summary,
+    chunk: &ChunkIndex,
+    channel_ids: &HashSet<u16>,
+    range: TimeRange,
+) -> bool {
+    if range.contains(chunk.message_start_time) && range.contains(chunk.message_end_time) {
+        return true;
+    }
+    let indexes = match summary.read_message_indexes(mapped, chunk) {
+        Ok(v) => v,
+        Err(_) => return true,
+    };
+    indexes
+        .iter()
+        .filter(|(channel, _)| channel_ids.contains(&channel.id))
+        .flat_map(|(_, entries)| entries.iter())
+        .any(|e| range.contains(e.log_time))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{messages, TimeRange};
+    use mcap::{records::MessageHeader, Summary, WriteOptions};
+    use std::{
+        collections::{BTreeMap, HashSet},
+        io::Cursor,
+    };
+
+    /// Write `chunks` of `(topic, log_time)` messages, one MCAP chunk each,
+    /// or straight into the data section without chunk indexes.
+    pub fn recording(chunks: &[&[(&str, u64)]], indexed: bool) -> Vec<u8> {
+        let options = WriteOptions::new().chunk_size(None).use_chunks(indexed);
+        let mut writer = options.create(Cursor::new(Vec::new())).unwrap();
+        let mut channels = BTreeMap::new();
+        let mut sequence = 0;
+        for chunk in chunks {
+            for &(topic, log_time) in chunk.iter() {
+                let channel_id = match channels.get(topic) {
+                    Some(&id) => id,
+                    None => {
+                        let id = writer
+                            .add_channel(0, topic, "cdr", &BTreeMap::new())
+                            .unwrap();
+                        channels.insert(topic, id);
+                        id
+                    }
+                };
+                let header = MessageHeader {
+                    channel_id,
+                    sequence,
+                    log_time,
+                    publish_time: log_time,
+                };
+                writer.write_to_known_channel(&header, &[]).unwrap();
+                sequence += 1;
+            }
+            writer.flush().unwrap();
+        }
+        writer.finish().unwrap();
+        writer.into_inner().into_inner()
+    }
+
+    /// Replay `mapped` and list the `(topic, log_time)` of every message.
+    fn replay(mapped: &[u8], topics: &[&str], start: u64, end: u64) -> Vec<(String, u64)> {
+        let summary = Summary::read(mapped).unwrap();
+        let topics: HashSet<String> = topics.iter().map(|t| t.to_string()).collect();
+        messages(mapped, summary.as_ref(), &topics, TimeRange { start, end })
+            .unwrap()
+            .map(|m| {
+                let m = m.unwrap();
+                (m.channel.topic.clone(), m.log_time)
+            })
+            .collect()
+    }
+
+    fn expected(messages: &[(&str, u64)]) -> Vec<(String, u64)> {
+        messages.iter().map(|&(t, l)| (t.to_owned(), l)).collect()
+    }
+
+    #[test]
+    fn test_seek_indexed() {
+        let mapped = recording(
+            &[
+                &[("/a", 0), ("/b", 5), ("/a", 10)],
+                &[("/a", 20), ("/b", 25), ("/a", 30)],
+                // Overlaps the chunk after it.
+                &[("/a", 40), ("/b", 60)],
+                &[("/a", 50), ("/b", 55), ("/a", 60), ("/a", 70)],
+            ],
+            true,
+        );
+        let summary = Summary::read(&mapped).unwrap().unwrap();
+        assert_eq!(summary.chunk_indexes.len(), 4);
+
+        assert_eq!(
+            replay(&mapped, &["/a"], 10, 56),
+            expected(&[("/a", 10), ("/a", 20), ("/a", 30), ("/a", 40), ("/a", 50)])
+        );
+        // Overlapping chunks are merged by log time, ties in file order.
+        assert_eq!(
+            replay(&mapped, &["/a", "/b"], 25, 70),
+            expected(&[
+                ("/b", 25),
+                ("/a", 30),
+                ("/a", 40),
+                ("/a", 50),
+                ("/b", 55),
+                ("/b", 60),
+                ("/a", 60),
+            ])
+        );
+        // The second chunk overlaps the range, but only with /b.
+        assert_eq!(replay(&mapped, &["/a"], 21, 29), expected(&[]));
+        assert_eq!(replay(&mapped, &["/b"], 0, u64::MAX).len(), 4);
+    }
+
+    #[test]
+    fn test_seek_unindexed() {
+        let mapped = recording(
+            &[&[
+                ("/a", 0),
+                ("/b", 5),
+                ("/a", 10),
+                ("/a", 20),
+                ("/b", 25),
+                ("/a", 30),
+                // Out of order, past the end of the range read below.
+                ("/a", 15),
+            ]],
+            false,
+        );
+        let summary = Summary::read(&mapped).unwrap();
+        assert!(summary.is_none_or(|s| s.chunk_indexes.is_empty()));
+
+        assert_eq!(
+            replay(&mapped, &["/a"], 10, 30),
+            expected(&[("/a", 10), ("/a", 20)])
+        );
+        assert_eq!(
+            replay(&mapped, &["/a", "/b"], 5, 26),
+            expected(&[("/b", 5), ("/a", 10), ("/a", 20), ("/b", 25)])
+        );
+        assert_eq!(replay(&mapped, &["/a"], 0, u64::MAX).len(), 5);
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replay status and progress published as JSON on a Zenoh topic.
+//!
+//! A background thread publishes a `progress` message every
+//! `--status-interval`. The replay loop additionally publishes a
+//! `loop_start` event at the start of every pass over the recording, an
+//! `end` event when a `--one-shot` replay completes and a `stopped` event on
+//! Ctrl-C, so test harnesses can wait for completion. Every message carries
+//! the late and dropped message counts of each topic that has fallen behind.
+
+use crate::{
+    late::LateCounters,
+    playback::{Playback, PlaybackStatus},
+    source::TimeRange,
+};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+use zenoh::{bytes::Encoding, Session, Wait};
+
+pub struct StatusPublisher {
+    session: Session,
+    topic: String,
+    report: Report,
+}
+
+/// What every status message reports besides the playback state.
+struct Report {
+    /// Timeline start and path of every recording in the playlist.
+    files: Vec<(u64, String)>,
+    /// Replay range clipped to the recording's known extent; `end` is
+    /// `u64::MAX` when the summary doesn't record the last log time.
+    range: TimeRange,
+    late: Arc<LateCounters>,
+}
+
+impl StatusPublisher {
+    pub fn new(
+        session: Session,
+        topic: String,
+        files: Vec<(u64, String)>,
+        range: TimeRange,
+        late: Arc<LateCounters>,
+    ) -> Self {
+        Self {
+            session,
+            topic,
+            report: Report { files, range, late },
+        }
+    }
+
+    /// Publish a `progress` message every `interval` until `run` is cleared.
+    pub fn spawn(
+        self: Arc<Self>,
+        playback: Arc<Playback>,
+        interval: Duration,
+        run: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut last: Option<(u64, Instant)> = None;
+            while run.load(Ordering::Relaxed) {
+                sleep(interval);
+                let status = playback.status();
+                if status.iteration == 0 {
+                    continue;
+                }
+                // Effective speed is measured from the published message
+                // positions, so it drops below the requested speed when the
+                // loop can't keep up.
+                let now = Instant::now();
+                let effective = match last {
+                    Some((position, at)) if status.position >= position => {
+                        let wall = now.duration_since(at).as_secs_f64();
+                        (status.position - position) as f64 / 1e9 / wall
+                    }
+                    _ => 0.0,
+                };
+                last = Some((status.position, now));
+
+                let mut msg = self.report.message("progress", &status);
+                msg["effective_speed"] = json!(effective);
+                self.put(&msg);
+            }
+        })
+    }
+
+    /// Publish a one-off event such as `loop_start` or `end`.
+    pub fn event(&self, event: &str, status: &PlaybackStatus) {
+        debug!("Replay status event '{event}' (loop {})", status.iteration);
+        self.put(&self.report.message(event, status));
+    }
+
+    fn put(&self, msg: &Value) {
+        if let Err(e) = self
+            .session
+            .put(&self.topic, msg.to_string())
+            .encoding(Encoding::APPLICATION_JSON)
+            .wait()
+        {
+            warn!("Error sending replay status on {}: {:?}", self.topic, e);
+        }
+    }
+}
+
+impl Report {
+    fn message(&self, event: &str, status: &PlaybackStatus) -> Value {
+        let percent = if self.range.end == u64::MAX || self.range.end <= self.range.start {
+            Value::Null
+        } else {
+            let done = status.position.saturating_sub(self.range.start) as f64;
+            let total = (self.range.end - self.range.start) as f64;
+            json!((done / total * 100.0).clamp(0.0, 100.0))
+        };
+        // The file being played is the last one starting at or before the
+        // current position.
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= status.position)
+            .or(self.files.first())
+            .map(|(_, path)| path.as_str());
+        let late: serde_json::Map<String, Value> = self
+            .late
+            .snapshot()
+            .into_iter()
+            .map(|(topic, count)| {
+                let count = json!({ "late": count.late, "dropped": count.dropped });
+                (topic, count)
+            })
+            .collect();
+        json!({
+            "event": event,
+            "file": file,
+            "log_time": status.position,
+            "percent": percent,
+            "loop": status.iteration,
+            "speed": status.speed,
+            "afap": status.afap,
+            "paused": status.paused,
+            "lag_ms": status.lag.as_secs_f64() * 1e3,
+            "late": late,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::{late::LateCounters, playback::PlaybackStatus, source::TimeRange};
+    use serde_json::json;
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn test_message() {
+        let late = Arc::new(LateCounters::default());
+        late.record("/camera/h264", true);
+        let report = Report {
+            files: vec![(100, "a.mcap".to_owned()), (300, "b.mcap".to_owned())],
+            range: TimeRange {
+                start: 100,
+                end: 500,
+            },
+            late,
+        };
+        let status = PlaybackStatus {
+            paused: true,
+            speed: 0.5,
+            afap: false,
+            log_time: 400,
+            position: 400,
+            lag: Duration::from_millis(2),
+            iteration: 3,
+        };
+        let msg = report.message("progress", &status);
+        assert_eq!(
+            msg,
+            json!({
+                "event": "progress",
+                "file": "b.mcap",
+                "log_time": 400,
+                "percent": 75.0,
+                "loop": 3,
+                "speed": 0.5,
+                "afap": false,
+                "paused": true,
+                "lag_ms": 2.0,
+                "late": { "/camera/h264": { "late": 1, "dropped": 1 } },
+            })
+        );
+
+        // Without a known end there is no percentage.
+        let report = Report {
+            range: TimeRange {
+                start: 100,
+                end: u64::MAX,
+            },
+            ..report
+        };
+        assert_eq!(report.message("end", &status)["percent"], json!(null));
+    }
+}
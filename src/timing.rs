@@ -0,0 +1,193 @@
+// Copyright 2025 Au-Zone Technologies Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timestamp source for pacing, from `--timing`.
+//!
+//! Replay paces messages by their MCAP log time, i.e. when the recorder
+//! received them, which jitters when the recorder runs on a loaded host.
+//! `--timing publish` uses the MCAP publish time instead and `--timing
+//! header` the stamp in the message's own header, for the schemas whose
+//! stamp [`restamp`] can find, so replay reproduces the sensor cadence.
+//!
+//! The chosen time replaces each message's log time as it is read from the
+//! file, before the file is placed on the playlist timeline, so pacing,
+//! late policies, restamping and status all follow it. `--start`, `--end`
+//! and seeks still select messages by log time. Messages are put back in
+//! order within a window of [`WINDOW`], and a message without a usable time
+//! (no known header, an unset stamp, or one further than [`WINDOW`] from
+//! its log time, e.g. a sensor on its own clock) keeps its log time.
+
+use crate::{restamp, source::MessageIter};
+use clap::ValueEnum;
+use log::warn;
+use mcap::{McapResult, Message};
+use std::collections::{BTreeMap, HashSet};
+
+/// Furthest a message's chosen time may be from its log time.
+pub const WINDOW: u64 = 1_000_000_000;
+
+/// Where the time replay paces a message by comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Timing {
+    /// MCAP log time: when the recorder received the message.
+    #[default]
+    Log,
+    /// MCAP publish time: when the publisher sent the message.
+    Publish,
+    /// The stamp in the message's header.
+    Header,
+}
+
+impl Timing {
+    /// The chosen time of `message`, if it has one.
+    fn time(&self, message: &Message) -> Option<u64> {
+        let time = match self {
+            Timing::Log => return Some(message.log_time),
+            Timing::Publish => message.publish_time,
+            Timing::Header => {
+                let schema = message.channel.schema.as_ref()?;
+                if !restamp::has_stamp(&schema.name) {
+                    return None;
+                }
+                restamp::stamp(&message.data).ok()?.to_nanos()?
+            }
+        };
+        (time != 0).then_some(time)
+    }
+}
+
+/// Re-time a file's message stream by `timing`, keeping it in order.
+pub fn retime<'a>(messages: MessageIter<'a>, timing: Timing) -> MessageIter<'a> {
+    if timing == Timing::Log {
+        return messages;
+    }
+    Box::new(Retime {
+        messages,
+        timing,
+        held: BTreeMap::new(),
+        read: 0,
+        seq: 0,
+        done: false,
+        warned: HashSet::new(),
+    })
+}
+
+/// Holds messages back until no message still to be read can come before
+/// them: the stream is in log time order and every chosen time is within
+/// [`WINDOW`] of its log time.
+struct Retime<'a> {
+    messages: MessageIter<'a>,
+    timing: Timing,
+    /// Messages read but not yet yielded, by chosen time and read order.
+    held: BTreeMap<(u64, u64), Message<'a>>,
+    /// Latest log time read.
+    read: u64,
+    seq: u64,
+    done: bool,
+    /// Topics already warned about falling back to log time.
+    warned: HashSet<String>,
+}
+
+impl<'a> Iterator for Retime<'a> {
+    type Item = McapResult<Message<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.held.first_entry() {
+                let (time, _) = *entry.key();
+                if self.done || time.saturating_add(WINDOW) <= self.read {
+                    return Some(Ok(entry.remove()));
+                }
+            } else if self.done {
+                return None;
+            }
+            let mut message = match self.messages.next() {
+                Some(Ok(v)) => v,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    continue;
+                }
+            };
+            self.read = self.read.max(message.log_time);
+            let time = self
+                .timing
+                .time(&message)
+                .filter(|t| t.abs_diff(message.log_time) <= WINDOW);
+            match time {
+                Some(t) => message.log_time = t,
+                None => {
+                    if self.warned.insert(message.channel.topic.clone()) {
+                        warn!(
+                            "No usable {:?} time on {}; pacing it by log time",
+                            self.timing, message.channel.topic
+                        );
+                    }
+                }
+            }
+            self.held.insert((message.log_time, self.seq), message);
+            self.seq += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retime, Timing, WINDOW};
+    use crate::restamp::set_stamp;
+    use edgefirst_schemas::{builtin_interfaces::Time, std_msgs::Header};
+    use mcap::{Channel, Message, Schema};
+    use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
+
+    fn message(channel: &Arc<Channel<'static>>, log_time: u64, stamp: u64) -> Message<'static> {
+        let mut data = Header::builder()
+            .frame_id("lidar")
+            .build()
+            .unwrap()
+            .into_cdr();
+        set_stamp(&mut data, Time::from_nanos(stamp)).unwrap();
+        Message {
+            channel: channel.clone(),
+            sequence: 0,
+            log_time,
+            publish_time: stamp,
+            data: Cow::Owned(data),
+        }
+    }
+
+    #[test]
+    fn test_retime() {
+        let schema = Schema {
+            id: 1,
+            name: "std_msgs/msg/Header".to_owned(),
+            encoding: "ros2msg".to_owned(),
+            data: Cow::Borrowed(&[]),
+        };
+        let channel = Arc::new(Channel {
+            id: 1,
+            topic: "/lidar".to_owned(),
+            schema: Some(Arc::new(schema)),
+            message_encoding: "cdr".to_owned(),
+            metadata: BTreeMap::new(),
+        });
+        let ms = 1_000_000;
+        // Log times jitter; the header stamps are 10 ms apart, and the last
+        // stamp is too far from its log time to be trusted.
+        let messages = [
+            message(&channel, 100 * ms + 5 * ms, 100 * ms),
+            message(&channel, 100 * ms + 9 * ms, 120 * ms),
+            message(&channel, 100 * ms + 10 * ms, 110 * ms),
+            message(&channel, 200 * ms, 200 * ms + WINDOW + 1),
+        ];
+        for (timing, expected) in [
+            (Timing::Log, [105, 109, 110, 200]),
+            (Timing::Header, [100, 110, 120, 200]),
+            (Timing::Publish, [100, 110, 120, 200]),
+        ] {
+            let times: Vec<u64> = retime(Box::new(messages.clone().into_iter().map(Ok)), timing)
+                .map(|m| m.unwrap().log_time / ms)
+                .collect();
+            assert_eq!(times, expected, "{timing:?}");
+        }
+    }
+}
@@ -1,55 +1,157 @@
 // Copyright 2025 Au-Zone Technologies Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-//! H.264 (VPU) and JPEG (hal codec) decoders for the replay pipeline.
+//! H.264/H.265 (VPU or software) and JPEG (hal codec) decoders for the
+//! replay pipeline.
 //!
-//! H.264 frames are surfaced directly as `videostream::Frame` — the caller
-//! publishes the decoder-native NV12 buffer to `rt/camera/dma` and optionally
-//! converts to RGBA via the hal `ImageProcessor` for `rt/camera/image`.
+//! The codec of a `CompressedVideo` stream comes from the message's `format`
+//! field. VPU frames are surfaced directly as `videostream::Frame` — the
+//! caller publishes the decoder-native NV12 buffer to `rt/camera/dma` and
+//! optionally converts to RGBA via the hal `ImageProcessor` for
+//! `rt/camera/image`.
+//!
+//! Off-target, where there is no VPU, H.264 is decoded on the CPU with
+//! OpenH264 and H.265 with libde265, and the I420 output is converted into a
+//! ring of NV12 hal tensors, dma-buf backed where a dma-heap is available
+//! and shared memory otherwise. Those frames follow the same tensor publish
+//! path as JPEG.
 //!
 //! JPEG frames are decoded by `edgefirst_codec::ImageDecoder` directly into
 //! a pre-allocated NV12 dma-buf tensor ring — no host-side intermediate,
 //! no memcpy.
+//!
+//! Both rings are reallocated when the frame size changes mid-stream. The
+//! old buffers were published by fd, so they stay open until the new ring
+//! has gone round once — the same time a slot is otherwise left alone before
+//! being reused — and a late receiver never imports a closed or recycled fd.
 
+use crate::de265;
 use edgefirst_codec::{peek_info, DecodeOptions, ImageDecoder, ImageLoad};
 use edgefirst_hal::image::ImageProcessor;
-use edgefirst_hal::tensor::{DType, PixelFormat, TensorDyn};
-use log::{info, trace, warn};
-use std::{error::Error, thread::sleep, time::Duration};
+use edgefirst_hal::tensor::{DType, PixelFormat, TensorDyn, TensorMemory, TensorTrait};
+use log::{debug, info, trace, warn};
+use openh264::formats::YUVSource;
+use std::{
+    error::Error,
+    fmt, mem,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    thread::sleep,
+    time::Duration,
+};
 use videostream::decoder::{CodecBackend, DecodeReturnCode, Decoder, DecoderCodec};
 use videostream::encoder::VSLRect;
 use videostream::frame::Frame;
 
 const JPEG_RING_DEPTH: usize = 4;
+const SOFTWARE_RING_DEPTH: usize = 4;
+
+/// Video decoder selection for `--decoder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DecoderBackend {
+    /// VPU when available, otherwise software.
+    Auto,
+    /// VPU only.
+    Hardware,
+    /// CPU decoding with OpenH264 (H.264) or libde265 (H.265).
+    Software,
+}
+
+/// Codec of a `CompressedVideo` stream, from its `format` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+}
+
+impl VideoCodec {
+    pub fn from_format(format: &str) -> Option<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "h264" | "avc" => Some(VideoCodec::H264),
+            "h265" | "hevc" => Some(VideoCodec::H265),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoCodec::H264 => write!(f, "H.264"),
+            VideoCodec::H265 => write!(f, "H.265"),
+        }
+    }
+}
+
+impl From<VideoCodec> for DecoderCodec {
+    fn from(codec: VideoCodec) -> Self {
+        match codec {
+            VideoCodec::H264 => DecoderCodec::H264,
+            VideoCodec::H265 => DecoderCodec::HEVC,
+        }
+    }
+}
+
+/// A `CompressedVideo` decoder on the VPU or on the CPU.
+pub enum CompressedVideoDecoder {
+    Hardware(VideoDecoder),
+    Software(SoftwareDecoder),
+}
+
+impl CompressedVideoDecoder {
+    pub fn new(codec: VideoCodec, backend: DecoderBackend) -> Result<Self, Box<dyn Error>> {
+        use CompressedVideoDecoder::{Hardware, Software};
+        match backend {
+            DecoderBackend::Hardware => Ok(Hardware(VideoDecoder::new(codec)?)),
+            DecoderBackend::Software => Ok(Software(SoftwareDecoder::new(codec)?)),
+            DecoderBackend::Auto => match VideoDecoder::new(codec) {
+                Ok(v) => Ok(Hardware(v)),
+                Err(e) => {
+                    warn!("No hardware {codec} decoder ({e:?}), decoding in software");
+                    Ok(Software(SoftwareDecoder::new(codec)?))
+                }
+            },
+        }
+    }
+
+    pub fn codec(&self) -> VideoCodec {
+        match self {
+            CompressedVideoDecoder::Hardware(v) => v.codec,
+            CompressedVideoDecoder::Software(v) => v.codec,
+        }
+    }
+}
 
 pub struct VideoDecoder {
     decoder: Decoder,
+    codec: VideoCodec,
     last_data: Vec<u8>,
-    visible_logged: bool,
+    /// Last visible size logged, to report mid-stream size changes.
+    visible: Option<(i32, i32)>,
     pub frame_count: usize,
 }
 
 impl VideoDecoder {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    pub fn new(codec: VideoCodec) -> Result<Self, Box<dyn Error>> {
         // Use the explicit Auto-backend constructor. The legacy `create()`
         // goes through the older `vsl_decoder_create` C entry which doesn't
         // run the v4l2 device enumeration the 2.5.x stack relies on; the
         // `_ex` entry does.
         Ok(VideoDecoder {
-            decoder: Decoder::create_ex(DecoderCodec::H264, 30, CodecBackend::Auto)?,
+            decoder: Decoder::create_ex(codec.into(), 30, CodecBackend::Auto)?,
+            codec,
             last_data: Vec::new(),
-            visible_logged: false,
+            visible: None,
             frame_count: 0,
         })
     }
 
-    /// Visible (post-crop) frame rectangle as reported by the H264 decoder.
+    /// Visible (post-crop) frame rectangle as reported by the decoder.
     /// Only valid after the first frame has decoded.
     pub fn crop(&self) -> Result<VSLRect, Box<dyn Error>> {
         Ok(self.decoder.crop()?)
     }
 
-    /// Decode one H264 message and return the next NV12 frame, if any.
+    /// Decode one video message and return the next NV12 frame, if any.
     ///
     /// The returned `Frame` borrows a slot from the VPU pool — keep it alive
     /// across the publish call(s) so the receiver can still open its fd.
@@ -58,7 +160,7 @@ impl VideoDecoder {
     /// can be momentarily full and the backend returns an `Io("Decoder Error")`
     /// until a slot frees up. We retry the same data on error, sleeping
     /// briefly between attempts, matching the camera-service replay pattern.
-    pub fn decode_h264_msg(&mut self, data: &[u8]) -> Result<Option<Frame>, Box<dyn Error>> {
+    pub fn decode_msg(&mut self, data: &[u8]) -> Result<Option<Frame>, Box<dyn Error>> {
         const MAX_RETRIES: usize = 20;
         const RETRY_SLEEP: Duration = Duration::from_millis(5);
 
@@ -77,10 +179,16 @@ impl VideoDecoder {
                         self.last_data.len()
                     );
 
-                    if ret == DecodeReturnCode::Initialized && !self.visible_logged {
+                    if ret == DecodeReturnCode::Initialized {
                         let crop = self.decoder.crop()?;
-                        info!("Video dimensions are: {}x{}", crop.width(), crop.height());
-                        self.visible_logged = true;
+                        let visible = (crop.width(), crop.height());
+                        if self.visible != Some(visible) {
+                            info!(
+                                "{} video dimensions are: {}x{}",
+                                self.codec, visible.0, visible.1
+                            );
+                            self.visible = Some(visible);
+                        }
                     }
 
                     if let Some(f) = frame {
@@ -111,6 +219,209 @@ impl VideoDecoder {
     }
 }
 
+/// A software-decoded NV12 frame and the fd published for it.
+pub struct SoftwareFrame {
+    pub tensor: TensorDyn,
+    /// Kept open for the lifetime of the slot so receivers can import the
+    /// buffer by fd number, whether it is a dma-buf or shared memory.
+    fd: OwnedFd,
+}
+
+impl SoftwareFrame {
+    pub fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+enum SoftwareCodec {
+    H264(openh264::decoder::Decoder),
+    H265(de265::Decoder),
+}
+
+/// CPU video decoder writing NV12 into a ring of fd-backed hal tensors.
+pub struct SoftwareDecoder {
+    decoder: SoftwareCodec,
+    codec: VideoCodec,
+    ring: FrameRing,
+    pub frame_count: usize,
+}
+
+impl SoftwareDecoder {
+    pub fn new(codec: VideoCodec) -> Result<Self, Box<dyn Error>> {
+        let decoder = match codec {
+            VideoCodec::H264 => SoftwareCodec::H264(openh264::decoder::Decoder::new()?),
+            VideoCodec::H265 => SoftwareCodec::H265(de265::Decoder::new()?),
+        };
+        Ok(SoftwareDecoder {
+            decoder,
+            codec,
+            ring: FrameRing::default(),
+            frame_count: 0,
+        })
+    }
+
+    /// Decode one video message and return the ring slot holding the next
+    /// NV12 frame, if any. The slot is reused `SOFTWARE_RING_DEPTH` frames
+    /// later.
+    pub fn decode_msg(&mut self, data: &[u8]) -> Result<Option<&SoftwareFrame>, Box<dyn Error>> {
+        let index = match &mut self.decoder {
+            SoftwareCodec::H264(d) => match d.decode(data)? {
+                Some(yuv) => self.ring.write(&yuv, self.codec)?,
+                None => return Ok(None),
+            },
+            SoftwareCodec::H265(d) => match d.decode(data)? {
+                Some(yuv) => self.ring.write(&yuv, self.codec)?,
+                None => return Ok(None),
+            },
+        };
+        self.frame_count += 1;
+        Ok(Some(&self.ring.frames[index]))
+    }
+}
+
+/// NV12 destination ring, reallocated when the video dimensions change.
+#[derive(Default)]
+struct FrameRing {
+    frames: Vec<SoftwareFrame>,
+    next: usize,
+    retired: Retired<SoftwareFrame>,
+}
+
+impl FrameRing {
+    /// Convert `yuv` into the next slot and return its index.
+    fn write(&mut self, yuv: &impl YUVSource, codec: VideoCodec) -> Result<usize, Box<dyn Error>> {
+        let (width, height) = yuv.dimensions();
+        let (nv12_width, nv12_height) = nv12_dimensions(width, height);
+        let dims = self
+            .frames
+            .first()
+            .map(|f| (f.tensor.width(), f.tensor.height()));
+        if dims != Some((Some(nv12_width), Some(nv12_height))) {
+            info!("{codec} video dimensions are: {width}x{height} (software decoding to NV12)");
+            let mut frames = Vec::with_capacity(SOFTWARE_RING_DEPTH);
+            for _ in 0..SOFTWARE_RING_DEPTH {
+                frames.push(alloc_nv12(nv12_width, nv12_height)?);
+            }
+            self.retired
+                .hold(mem::replace(&mut self.frames, frames), SOFTWARE_RING_DEPTH);
+            self.next = 0;
+        }
+        self.retired.tick();
+
+        let index = self.next;
+        self.next = (index + 1) % self.frames.len();
+        let slot = &mut self.frames[index];
+        let stride = slot.tensor.effective_row_stride().unwrap_or(nv12_width);
+        let tensor = slot
+            .tensor
+            .as_u8_mut()
+            .ok_or("software decoder frame is not a u8 tensor")?;
+        let mut map = tensor.map()?;
+        i420_to_nv12(yuv, &mut map, stride);
+        Ok(index)
+    }
+}
+
+/// Buffers of a replaced ring, kept open for a number of frames after the
+/// replacement because receivers may still import them.
+struct Retired<T> {
+    items: Vec<T>,
+    frames_left: usize,
+}
+
+impl<T> Default for Retired<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            frames_left: 0,
+        }
+    }
+}
+
+impl<T> Retired<T> {
+    /// Keep `items` for the next `frames` frames, along with anything still
+    /// held from an earlier replacement.
+    fn hold(&mut self, items: Vec<T>, frames: usize) {
+        if !items.is_empty() {
+            self.items.extend(items);
+            self.frames_left = frames;
+        }
+    }
+
+    /// Count one frame written to the new ring, releasing the retired
+    /// buffers once it has gone round.
+    fn tick(&mut self) {
+        if self.frames_left > 0 {
+            self.frames_left -= 1;
+            if self.frames_left == 0 {
+                debug!("Releasing {} retired frame buffers", self.items.len());
+                self.items.clear();
+            }
+        }
+    }
+}
+
+/// Allocate an NV12 image with an fd receivers can import: a dma-buf when a
+/// dma-heap is available, shared memory otherwise.
+fn alloc_nv12(width: usize, height: usize) -> Result<SoftwareFrame, Box<dyn Error>> {
+    let tensor = match TensorDyn::image(
+        width,
+        height,
+        PixelFormat::Nv12,
+        DType::U8,
+        Some(TensorMemory::Dma),
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            trace!("dma-buf allocation failed ({e:?}), using shared memory");
+            TensorDyn::image(
+                width,
+                height,
+                PixelFormat::Nv12,
+                DType::U8,
+                Some(TensorMemory::Shm),
+            )?
+        }
+    };
+    let fd = tensor.clone_fd()?;
+    Ok(SoftwareFrame { tensor, fd })
+}
+
+/// Size of the NV12 image holding a `width`x`height` frame: rounded up to
+/// whole 2x2 chroma blocks.
+fn nv12_dimensions(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2) * 2, height.div_ceil(2) * 2)
+}
+
+/// Copy planar I420 into semi-planar NV12 with luma row stride `stride`,
+/// which must hold a whole row of chroma pairs. Odd frame sizes are padded
+/// to whole chroma blocks by repeating the last luma column and row.
+fn i420_to_nv12(yuv: &impl YUVSource, dst: &mut [u8], stride: usize) {
+    let (width, height) = yuv.dimensions();
+    let (nv12_width, nv12_height) = nv12_dimensions(width, height);
+    let (y_stride, u_stride, v_stride) = yuv.strides();
+    let (y, u, v) = (yuv.y(), yuv.u(), yuv.v());
+
+    for row in 0..nv12_height {
+        let src = row.min(height - 1) * y_stride;
+        let dst_row = &mut dst[row * stride..row * stride + nv12_width];
+        dst_row[..width].copy_from_slice(&y[src..src + width]);
+        if nv12_width > width {
+            dst_row[width] = dst_row[width - 1];
+        }
+    }
+    let uv_plane = &mut dst[stride * nv12_height..];
+    for row in 0..nv12_height / 2 {
+        let dst_row = &mut uv_plane[row * stride..row * stride + nv12_width];
+        let u_row = &u[row * u_stride..];
+        let v_row = &v[row * v_stride..];
+        for col in 0..nv12_width / 2 {
+            dst_row[col * 2] = u_row[col];
+            dst_row[col * 2 + 1] = v_row[col];
+        }
+    }
+}
+
 /// Hal-backed JPEG stream: decodes JPEGs directly into a ring of NV12
 /// dma-buf tensors so the published fd is the same one the codec wrote into.
 pub struct JpegStream {
@@ -118,6 +429,7 @@ pub struct JpegStream {
     decoder: ImageDecoder,
     dst_ring: Vec<TensorDyn>,
     next_dst: usize,
+    retired: Retired<TensorDyn>,
 }
 
 impl JpegStream {
@@ -127,20 +439,24 @@ impl JpegStream {
             decoder: ImageDecoder::new(),
             dst_ring: Vec::new(),
             next_dst: 0,
+            retired: Retired::default(),
         })
     }
 
     /// Decode one JPEG message into the next NV12 dma-buf tensor slot and
-    /// return a reference to it.
+    /// return a reference to it. The ring is reallocated whenever the JPEG
+    /// size differs from the previous one.
     pub fn decode(&mut self, data: &[u8]) -> Result<&TensorDyn, Box<dyn Error>> {
         let opts = DecodeOptions::default().with_format(PixelFormat::Nv12);
 
-        if self.dst_ring.is_empty() {
-            let info = peek_info(data, &opts)?;
+        let info = peek_info(data, &opts)?;
+        let dims = self.dst_ring.first().map(|t| (t.width(), t.height()));
+        if dims != Some((Some(info.width), Some(info.height))) {
             info!(
                 "JPEG dimensions are: {}x{} (decoding to NV12)",
                 info.width, info.height
             );
+            let mut ring = Vec::with_capacity(JPEG_RING_DEPTH);
             for _ in 0..JPEG_RING_DEPTH {
                 let t = self.processor.create_image(
                     info.width,
@@ -149,9 +465,13 @@ impl JpegStream {
                     DType::U8,
                     None,
                 )?;
-                self.dst_ring.push(t);
+                ring.push(t);
             }
+            self.retired
+                .hold(mem::replace(&mut self.dst_ring, ring), JPEG_RING_DEPTH);
+            self.next_dst = 0;
         }
+        self.retired.tick();
 
         let idx = self.next_dst;
         self.next_dst = (self.next_dst + 1) % self.dst_ring.len();
@@ -159,3 +479,101 @@ impl JpegStream {
         Ok(&self.dst_ring[idx])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{i420_to_nv12, Retired, VideoCodec};
+    use openh264::formats::{YUVSlices, YUVSource};
+
+    #[test]
+    fn test_video_codec_from_format() {
+        assert_eq!(VideoCodec::from_format("h264"), Some(VideoCodec::H264));
+        assert_eq!(VideoCodec::from_format("h265"), Some(VideoCodec::H265));
+        assert_eq!(VideoCodec::from_format("HEVC"), Some(VideoCodec::H265));
+        assert_eq!(VideoCodec::from_format("vp9"), None);
+    }
+
+    #[test]
+    fn test_retired() {
+        let mut retired = Retired::default();
+        retired.hold(vec![1, 2], 2);
+        retired.tick();
+        assert_eq!(retired.items, [1, 2]);
+        // A second change before the first drained keeps both generations.
+        retired.hold(vec![3], 2);
+        retired.tick();
+        assert_eq!(retired.items, [1, 2, 3]);
+        retired.tick();
+        assert!(retired.items.is_empty());
+        retired.hold(Vec::new(), 2);
+        assert_eq!(retired.frames_left, 0);
+    }
+
+    #[test]
+    fn test_i420_to_nv12() {
+        // 4x2 frame with padded source strides, written to a stride of 6.
+        let y = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+        let u = [10, 11, 0];
+        let v = [20, 21, 0];
+        let yuv = YUVSlices::new((&y, &u, &v), (4, 2), (6, 3, 3));
+        let mut dst = [0xff; 6 * 3];
+        i420_to_nv12(&yuv, &mut dst, 6);
+        assert_eq!(
+            dst,
+            [1, 2, 3, 4, 0xff, 0xff, 5, 6, 7, 8, 0xff, 0xff, 10, 20, 11, 21, 0xff, 0xff]
+        );
+    }
+
+    /// Planes of an I420 frame of any size, which `YUVSlices` cannot
+    /// describe for odd heights.
+    struct Planes<'a> {
+        dimensions: (usize, usize),
+        strides: (usize, usize, usize),
+        y: &'a [u8],
+        u: &'a [u8],
+        v: &'a [u8],
+    }
+
+    impl YUVSource for Planes<'_> {
+        fn dimensions(&self) -> (usize, usize) {
+            self.dimensions
+        }
+
+        fn strides(&self) -> (usize, usize, usize) {
+            self.strides
+        }
+
+        fn y(&self) -> &[u8] {
+            self.y
+        }
+
+        fn u(&self) -> &[u8] {
+            self.u
+        }
+
+        fn v(&self) -> &[u8] {
+            self.v
+        }
+    }
+
+    #[test]
+    fn test_i420_to_nv12_odd() {
+        // 3x3 frame, padded to a 4x4 NV12 image of exactly its size.
+        let yuv = Planes {
+            dimensions: (3, 3),
+            strides: (3, 2, 2),
+            y: &[1, 2, 3, 4, 5, 6, 7, 8, 9],
+            u: &[10, 11, 12, 13],
+            v: &[20, 21, 22, 23],
+        };
+        let mut dst = [0xff; 4 * 6];
+        i420_to_nv12(&yuv, &mut dst, 4);
+        assert_eq!(
+            dst,
+            [
+                1, 2, 3, 3, 4, 5, 6, 6, 7, 8, 9, 9, 7, 8, 9, 9, // luma
+                10, 20, 11, 21, 12, 22, 13, 23, // chroma
+            ]
+        );
+    }
+}